@@ -0,0 +1,96 @@
+use std::{
+	collections::HashMap,
+	ops::Range,
+	path::{Path, PathBuf},
+};
+
+use rand::seq::IteratorRandom;
+
+use crate::{cmd, config, index};
+
+/// the tracks of the album `track` is on, in play order
+pub fn tracks(
+	config: &config::AlbumConfig,
+	playlist: &[PathBuf],
+	files: Range<usize>,
+	track: usize,
+	index: &HashMap<PathBuf, index::Entry>,
+) -> Vec<usize> {
+	let mediainfo = |x: usize| index.get(&playlist[x]).map(|x| &x.mediainfo);
+	let album = key(config, &playlist[track], mediainfo(track));
+	let mut tracks =
+		files.filter(|&x| key(config, &playlist[x], mediainfo(x)) == album).collect::<Vec<_>>();
+	sort(&mut tracks, playlist, mediainfo);
+	tracks
+}
+
+/// the next track of the current album, or the first one of another album once it's over
+pub fn next(
+	config: &config::AlbumConfig,
+	playlist: &[PathBuf],
+	files: Range<usize>,
+	current: usize,
+	index: &HashMap<PathBuf, index::Entry>,
+) -> usize {
+	let mediainfo = |x: usize| index.get(&playlist[x]).map(|x| &x.mediainfo);
+	let current = files.contains(&current).then_some(current);
+	if let Some(current) = current {
+		let album = tracks(config, playlist, files.clone(), current, index);
+		let position = album.iter().position(|&x| x == current);
+		if let Some(x) = position.and_then(|x| album.get(x + 1)) {
+			return *x;
+		}
+	}
+
+	let mut albums = HashMap::<_, Vec<_>>::new();
+	for x in files.clone() {
+		albums.entry(key(config, &playlist[x], mediainfo(x))).or_default().push(x);
+	}
+	if albums.len() > 1 {
+		if let Some(x) = current {
+			albums.remove(&key(config, &playlist[x], mediainfo(x)));
+		}
+	}
+	let Some(mut album) = albums.into_values().choose(&mut rand::thread_rng()) else {
+		return files.start;
+	};
+	sort(&mut album, playlist, mediainfo);
+	album[0]
+}
+
+fn key(config: &config::AlbumConfig, path: &Path, mediainfo: Option<&cmd::Mediainfo>) -> String {
+	let directory = path.parent().unwrap_or(path).to_string_lossy();
+	match config.group_by {
+		config::AlbumGrouping::Directory => directory.into_owned(),
+		// albums with the same name are told apart by artist, compilations by directory
+		config::AlbumGrouping::Tag => {
+			match mediainfo.and_then(|x| Some((x.album.as_deref()?, x))) {
+				Some((album, x)) => {
+					let artist = x.album_artist.as_deref().unwrap_or(&directory);
+					format!("{}\n{}", album.to_lowercase(), artist.to_lowercase())
+				}
+				None => directory.into_owned(),
+			}
+		}
+	}
+}
+
+/// the number in a disc or track tag. "3/12" is track 3 of 12
+pub fn number(tag: Option<&str>) -> Option<u32> {
+	tag?.split('/').next()?.trim().parse().ok()
+}
+
+fn sort<'a>(
+	tracks: &mut [usize],
+	playlist: &[PathBuf],
+	mediainfo: impl Fn(usize) -> Option<&'a cmd::Mediainfo>,
+) {
+	tracks.sort_by_cached_key(|&x| {
+		let mediainfo = mediainfo(x);
+		(
+			number(mediainfo.and_then(|x| x.disc.as_deref())).unwrap_or(1),
+			number(mediainfo.and_then(|x| x.track.as_deref())).unwrap_or(u32::MAX),
+			playlist[x].clone(),
+		)
+	});
+}
@@ -3,9 +3,13 @@ use std::{
 	time::Duration,
 };
 
+use axum::body::Bytes;
 use tokio::io::AsyncReadExt;
 
-use crate::cmd::{self};
+use crate::{
+	cmd::{self},
+	config::Codec,
+};
 
 #[derive(Debug)]
 pub enum Data {
@@ -31,16 +35,14 @@ pub struct FFMpegAudioReader {
 impl FFMpegAudioReader {
 	pub fn start(
 		input: impl AsRef<Path>,
-		sweeper: Option<impl AsRef<Path>>,
+		span: cmd::Span,
+		sweeper: Option<cmd::Sweeper>,
+		codec: Codec,
 		bitrate: u32,
 		copy_codec: bool,
 	) -> Self {
-		let mut handle = cmd::spawn_ffmpeg(
-			input.as_ref(),
-			sweeper.as_ref().map(|x| x.as_ref()),
-			bitrate,
-			copy_codec,
-		);
+		let mut handle =
+			cmd::spawn_ffmpeg(input.as_ref(), span, sweeper, codec, bitrate, copy_codec);
 		let stdout = handle.stdout.take().unwrap();
 		let stderr = handle.stderr.take().unwrap();
 		Self {
@@ -112,3 +114,317 @@ impl Drop for FFMpegAudioReader {
 		self.handle.start_kill().unwrap();
 	}
 }
+
+/// a piece of the broadcast. starts and ends on frame boundaries unless the codec isn't framed
+#[derive(Debug, Clone)]
+pub struct Chunk {
+	pub data: Bytes,
+	pub frames: u32,
+	pub duration: Duration,
+}
+
+impl Chunk {
+	pub const fn unframed(data: Bytes) -> Self {
+		Self { data, frames: 0, duration: Duration::ZERO }
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameFormat {
+	Mp3,
+	Adts,
+}
+
+/// splits encoder output on frame boundaries. partial frames are kept until the rest arrives
+pub struct Framer {
+	format: FrameFormat,
+	buf: Vec<u8>,
+}
+
+impl Framer {
+	/// there's no framer for ogg
+	pub const fn new(codec: Codec) -> Option<Self> {
+		let format = match codec {
+			Codec::Mp3 => FrameFormat::Mp3,
+			Codec::Aac => FrameFormat::Adts,
+			Codec::Opus => return None,
+		};
+		Some(Self { format, buf: Vec::new() })
+	}
+
+	/// returns all complete frames received so far. garbage between frames is dropped
+	pub fn push(&mut self, data: &[u8]) -> Chunk {
+		self.buf.extend_from_slice(data);
+
+		let mut out = Vec::with_capacity(self.buf.len());
+		let mut frames = 0;
+		let mut duration = Duration::ZERO;
+		let mut offset = 0;
+		while self.buf.len() - offset >= MAX_HEADER_LEN {
+			let header = match self.format {
+				FrameFormat::Mp3 => mp3_frame(&self.buf[offset..]),
+				FrameFormat::Adts => adts_frame(&self.buf[offset..]),
+			};
+			let Some(header) = header else {
+				offset += 1;
+				continue;
+			};
+			let Some(frame) = self.buf.get(offset..offset + header.len) else {
+				break;
+			};
+			out.extend_from_slice(frame);
+			frames += 1;
+			duration += header.duration();
+			offset += header.len;
+		}
+		self.buf.drain(..offset);
+
+		Chunk { data: out.into(), frames, duration }
+	}
+}
+
+// long enough for both mp3 and adts headers
+const MAX_HEADER_LEN: usize = 7;
+
+struct FrameHeader {
+	len: usize,
+	samples: u32,
+	sample_rate: u32,
+}
+
+impl FrameHeader {
+	fn duration(&self) -> Duration {
+		Duration::from_nanos(u64::from(self.samples) * 1_000_000_000 / u64::from(self.sample_rate))
+	}
+}
+
+fn mp3_frame(data: &[u8]) -> Option<FrameHeader> {
+	const BITRATES: [[u32; 14]; 5] = [
+		// mpeg 1 layer 1, 2, 3
+		[32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+		[32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+		[32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+		// mpeg 2 and 2.5 layer 1, then 2 and 3
+		[32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+		[8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+	];
+	const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+	let [a, b, c, ..] = *data else {
+		return None;
+	};
+	if a != 0xFF || b & 0xE0 != 0xE0 {
+		return None;
+	}
+	// 3: mpeg 1, 2: mpeg 2, 0: mpeg 2.5
+	let version = (b >> 3) & 0b11;
+	// 3: layer 1, 2: layer 2, 1: layer 3
+	let layer = (b >> 1) & 0b11;
+	let bitrate_index = (c >> 4) as usize;
+	let sample_rate_index = ((c >> 2) & 0b11) as usize;
+	let padding = u32::from((c >> 1) & 1);
+	// free format bitrate isn't supported
+	if version == 1 || layer == 0 || !(1..15).contains(&bitrate_index) || sample_rate_index == 3 {
+		return None;
+	}
+
+	let mpeg1 = version == 3;
+	let table = match (mpeg1, layer) {
+		(true, x) => 3 - x as usize,
+		(false, 3) => 3,
+		(false, _) => 4,
+	};
+	let bitrate = BITRATES[table][bitrate_index - 1] * 1000;
+	let sample_rate = SAMPLE_RATES[sample_rate_index]
+		>> match version {
+			3 => 0,
+			2 => 1,
+			_ => 2,
+		};
+
+	let (samples, len) = match (layer, mpeg1) {
+		(3, _) => (384, (12 * bitrate / sample_rate + padding) * 4),
+		(2, _) | (_, true) => (1152, 144 * bitrate / sample_rate + padding),
+		_ => (576, 72 * bitrate / sample_rate + padding),
+	};
+	Some(FrameHeader { len: len as usize, samples, sample_rate })
+}
+
+fn adts_frame(data: &[u8]) -> Option<FrameHeader> {
+	const SAMPLE_RATES: [u32; 13] =
+		[96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+
+	let [a, b, c, d, e, f, g, ..] = *data else {
+		return None;
+	};
+	if a != 0xFF || b & 0xF6 != 0xF0 {
+		return None;
+	}
+	let sample_rate = *SAMPLE_RATES.get(((c >> 2) & 0xF) as usize)?;
+	let len = (usize::from(d & 0b11) << 11) | (usize::from(e) << 3) | usize::from(f >> 5);
+	let blocks = u32::from(g & 0b11) + 1;
+	if len < MAX_HEADER_LEN {
+		return None;
+	}
+	Some(FrameHeader { len, samples: 1024 * blocks, sample_rate })
+}
+
+/// collects the pages at the start of an ogg stream that carry the codec headers.
+/// they have to be sent again to anyone who joins in the middle of the stream.
+#[derive(Default)]
+pub struct OggHeaderCollector {
+	buf: Vec<u8>,
+	done: bool,
+}
+
+impl OggHeaderCollector {
+	// nobody puts this much into headers, something is off
+	const LIMIT: usize = 64 * 1024;
+
+	/// returns the headers once the first audio page shows up
+	pub fn push(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+		if self.done {
+			return None;
+		}
+		self.buf.extend_from_slice(data);
+
+		let mut offset = 0;
+		while let Some(page) = ogg_page(&self.buf[offset..]) {
+			// header pages have granule position 0
+			if page.granule != 0 {
+				self.done = true;
+				self.buf.truncate(offset);
+				return Some(std::mem::take(&mut self.buf));
+			}
+			offset += page.len;
+		}
+
+		if self.buf.len() > Self::LIMIT || !self.buf.starts_with(&b"OggS"[..self.buf.len().min(4)])
+		{
+			self.done = true;
+			self.buf = Vec::new();
+		}
+		None
+	}
+}
+
+struct OggPage {
+	granule: u64,
+	len: usize,
+}
+
+fn ogg_page(data: &[u8]) -> Option<OggPage> {
+	if data.len() < 27 || !data.starts_with(b"OggS") {
+		return None;
+	}
+	let segments = data[26] as usize;
+	let table = data.get(27..27 + segments)?;
+	let len = 27 + segments + table.iter().map(|&x| x as usize).sum::<usize>();
+	(data.len() >= len)
+		.then(|| OggPage { granule: u64::from_le_bytes(data[6..14].try_into().unwrap()), len })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// mpeg 1 layer 3, 128 kbps, 44.1 kHz, no padding: 417 bytes
+	fn mp3_frame(fill: u8) -> Vec<u8> {
+		let mut frame = vec![fill; 417];
+		frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+		frame
+	}
+
+	// aac lc, 44.1 kHz, stereo, one block
+	fn adts_frame(len: usize) -> Vec<u8> {
+		let mut frame = vec![0x11; len];
+		frame[..7].copy_from_slice(&[
+			0xFF,
+			0xF1,
+			0x50,
+			0x80 | (len >> 11) as u8,
+			(len >> 3) as u8,
+			((len & 7) << 5) as u8 | 0x1F,
+			0xFC,
+		]);
+		frame
+	}
+
+	fn ogg_page(granule: u64, data: &[u8]) -> Vec<u8> {
+		let mut page = b"OggS\0\0".to_vec();
+		page.extend_from_slice(&granule.to_le_bytes());
+		page.extend_from_slice(&[0; 12]);
+		page.push(1);
+		page.push(data.len() as u8);
+		page.extend_from_slice(data);
+		page
+	}
+
+	#[test]
+	fn mp3_chunks_end_on_frame_boundaries() {
+		let stream = [mp3_frame(1), mp3_frame(2), mp3_frame(3)].concat();
+		let mut framer = Framer::new(Codec::Mp3).unwrap();
+
+		// the third frame is cut off in the middle
+		let chunk = framer.push(&stream[..417 * 2 + 100]);
+		assert_eq!(chunk.frames, 2);
+		assert_eq!(&chunk.data[..], &stream[..417 * 2]);
+		assert_eq!(chunk.duration, 2 * Duration::from_nanos(1152 * 1_000_000_000 / 44100));
+
+		let chunk = framer.push(&stream[417 * 2 + 100..]);
+		assert_eq!(chunk.frames, 1);
+		assert_eq!(&chunk.data[..], &stream[417 * 2..]);
+	}
+
+	#[test]
+	fn mp3_partial_header_waits_for_more() {
+		let frame = mp3_frame(1);
+		let mut framer = Framer::new(Codec::Mp3).unwrap();
+		assert!(framer.push(&frame[..3]).data.is_empty());
+		let chunk = framer.push(&frame[3..]);
+		assert_eq!(chunk.frames, 1);
+		assert_eq!(&chunk.data[..], &frame[..]);
+	}
+
+	#[test]
+	fn garbage_between_frames_is_dropped() {
+		let stream = [vec![0x00, 0x12, 0xFF], mp3_frame(1), vec![0x42; 5], mp3_frame(2)].concat();
+		let mut framer = Framer::new(Codec::Mp3).unwrap();
+		let chunk = framer.push(&stream);
+		assert_eq!(chunk.frames, 2);
+		assert_eq!(&chunk.data[..], &[mp3_frame(1), mp3_frame(2)].concat()[..]);
+	}
+
+	#[test]
+	fn adts_frames_use_the_length_from_the_header() {
+		let stream = [adts_frame(100), adts_frame(250), adts_frame(60)].concat();
+		let mut framer = Framer::new(Codec::Aac).unwrap();
+		let chunk = framer.push(&stream[..100 + 250 + 10]);
+		assert_eq!(chunk.frames, 2);
+		assert_eq!(&chunk.data[..], &stream[..350]);
+		assert_eq!(chunk.duration, 2 * Duration::from_nanos(1024 * 1_000_000_000 / 44100));
+	}
+
+	#[test]
+	fn opus_isnt_framed() {
+		assert!(Framer::new(Codec::Opus).is_none());
+	}
+
+	#[test]
+	fn ogg_headers_end_at_the_first_audio_page() {
+		let headers = [ogg_page(0, b"OpusHead"), ogg_page(0, b"OpusTags")].concat();
+		let audio = ogg_page(960, &[7; 20]);
+		let mut collector = OggHeaderCollector::default();
+		assert_eq!(collector.push(&headers[..10]), None);
+		assert_eq!(collector.push(&headers[10..]), None);
+		assert_eq!(collector.push(&audio), Some(headers));
+		assert_eq!(collector.push(&audio), None);
+	}
+
+	#[test]
+	fn ogg_collector_gives_up_on_other_data() {
+		let mut collector = OggHeaderCollector::default();
+		assert_eq!(collector.push(b"ID3 not ogg"), None);
+		assert_eq!(collector.push(&ogg_page(960, &[1])), None);
+	}
+}
@@ -1,11 +1,15 @@
 use std::{
+	io::{Read, Seek, SeekFrom},
 	path::{Path, PathBuf},
 	process::Stdio,
+	sync::OnceLock,
 };
 
 use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 
+use crate::config::{self, Codec, SweeperPosition};
+
 pub fn check_executables() -> (bool, Vec<(String, bool)>) {
 	let info = ["ffmpeg", "ffprobe"]
 		.into_iter()
@@ -16,92 +20,229 @@ pub fn check_executables() -> (bool, Vec<(String, bool)>) {
 	(info.iter().all(|x| x.1), info)
 }
 
+/// only checked once
+pub fn has_ffmpeg() -> bool {
+	static HAS_FFMPEG: OnceLock<bool> = OnceLock::new();
+	*HAS_FFMPEG.get_or_init(|| check_executables().1.into_iter().all(|x| x.0 != "ffmpeg" || x.1))
+}
+
+/// which part of the input gets played, how loud, and what it fades into
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Span<'a> {
+	/// seconds to skip at the start
+	pub start: f32,
+	/// stop reading the input here, in seconds
+	pub end: Option<f32>,
+	/// in dB. anything but 0 needs a transcode
+	pub gain: f32,
+	pub crossfade: Option<Crossfade<'a>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Crossfade<'a> {
+	pub next: &'a Path,
+	/// seconds of the next track that are mixed into the end of this one
+	pub duration: f32,
+	/// same as `Span::gain`, for the next track
+	pub gain: f32,
+}
+
+impl Span<'_> {
+	fn input_args(&self, cmd: &mut Command, input: &Path) {
+		if self.start > 0.0 {
+			cmd.args(["-ss", &self.start.to_string()]);
+		}
+		if let Some(end) = self.end {
+			cmd.args(["-to", &end.to_string()]);
+		}
+		cmd.arg("-i").arg(input);
+	}
+
+	fn crossfade_input_args(&self, cmd: &mut Command) {
+		if let Some(crossfade) = self.crossfade {
+			cmd.args(["-t", &crossfade.duration.to_string(), "-i"]).arg(crossfade.next);
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sweeper<'a> {
+	pub path: &'a Path,
+	/// `None` is the classic liner mix
+	pub template: Option<&'a config::MixTemplate>,
+	/// seconds into the song where the sweeper starts. ignored by `before`
+	pub at: f32,
+}
+
+fn volume_filter(input: &str, gain: f32, output: &str) -> String {
+	format!("[{input}]volume={gain}dB[{output}]")
+}
+
 #[allow(clippy::option_if_let_else)]
 pub fn spawn_ffmpeg(
 	input: &Path,
-	sweeper: Option<&Path>,
+	span: Span,
+	sweeper: Option<Sweeper>,
+	codec: Codec,
 	bitrate_bps: u32,
 	copy_codec: bool,
 ) -> tokio::process::Child {
 	if let Some(sweeper) = sweeper {
-		build_with_sweeper(input, sweeper, bitrate_bps)
+		build_with_sweeper(input, span, sweeper, codec, bitrate_bps)
 	} else {
-		build_without_sweeper(input, bitrate_bps, copy_codec)
+		build_without_sweeper(input, span, codec, bitrate_bps, copy_codec)
 	}
 	.kill_on_drop(true)
 	.spawn()
 	.unwrap()
 }
 
-fn build_without_sweeper(input: &Path, bitrate_bps: u32, copy_codec: bool) -> Command {
+/// transcodes a live source from stdin. it's already paced in real time, so there's no -re
+pub fn spawn_live_ffmpeg(codec: Codec, bitrate_bps: u32) -> tokio::process::Child {
+	let (encoder, format) = codec_args(codec);
+	let mut cmd = Command::new("ffmpeg");
+	cmd.args(["-hide_banner", "-loglevel", "fatal", "-threads", "1", "-i", "-"]).args([
+		"-c:a",
+		encoder,
+		"-b:a",
+		&bitrate_bps.to_string(),
+	]);
+	strip_metadata_args(&mut cmd, codec);
+	cmd.args(["-map", "0:a", "-f", format, "-"])
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.stdin(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()
+		.unwrap()
+}
+
+/// encoder and muxer for each output codec
+const fn codec_args(codec: Codec) -> (&'static str, &'static str) {
+	match codec {
+		Codec::Mp3 => ("mp3", "mp3"),
+		Codec::Opus => ("libopus", "ogg"),
+		Codec::Aac => ("aac", "adts"),
+	}
+}
+
+fn strip_metadata_args(cmd: &mut Command, codec: Codec) {
+	// these are mp3 muxer options
+	if codec == Codec::Mp3 {
+		cmd.args(["-write_xing", "0", "-id3v2_version", "0"]);
+	}
+	cmd.args(["-map_metadata", "-1", "-vn"]);
+}
+
+fn build_without_sweeper(
+	input: &Path,
+	span: Span,
+	codec: Codec,
+	bitrate_bps: u32,
+	copy_codec: bool,
+) -> Command {
+	let (encoder, format) = codec_args(codec);
 	let mut cmd = Command::new("ffmpeg");
-	cmd.args(["-hide_banner", "-loglevel", "fatal"])
-		.args(["-re", "-threads", "1", "-i"])
-		.arg(input);
-	if copy_codec {
+	cmd.args(["-hide_banner", "-loglevel", "fatal"]).args(["-re", "-threads", "1"]);
+	span.input_args(&mut cmd, input);
+	span.crossfade_input_args(&mut cmd);
+	let filter = match span.crossfade {
+		Some(crossfade) => Some(format!(
+			"{};{};[main][next]acrossfade=d={}[out]",
+			volume_filter("0:a", span.gain, "main"),
+			volume_filter("1:a", crossfade.gain, "next"),
+			crossfade.duration
+		)),
+		None if span.gain != 0.0 => Some(volume_filter("0:a", span.gain, "out")),
+		None => None,
+	};
+	// filtered audio has to be encoded
+	if copy_codec && filter.is_none() {
 		cmd.args(["-c:a", "copy"]);
 	} else {
-		cmd.args(["-c:a", "mp3", "-b:a", &bitrate_bps.to_string()]);
-	}
-	cmd.args([
-		"-write_xing",
-		"0",
-		"-id3v2_version",
-		"0",
-		"-map_metadata",
-		"-1",
-		"-vn",
+		cmd.args(["-c:a", encoder, "-b:a", &bitrate_bps.to_string()]);
+	}
+	strip_metadata_args(&mut cmd, codec);
+	if let Some(filter) = filter {
+		cmd.args(["-filter_complex", &filter, "-map", "[out]"]);
+	} else {
 		// this speeds up encoding a little for some reason
-		"-map",
-		"0:a",
-		"-f",
-		"mp3",
-		"-",
-	])
-	.stdout(Stdio::piped())
-	.stderr(Stdio::piped())
-	.stdin(Stdio::null());
+		cmd.args(["-map", "0:a"]);
+	}
+	cmd.args(["-f", format, "-"])
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.stdin(Stdio::null());
 	cmd
 }
 
-// temporarily permanent i think
-pub const SWEEPER_DIR: &str = "./sweepers";
-
 pub fn build_with_sweeper(
 	input: impl AsRef<Path>,
-	sweeper: impl AsRef<Path>,
+	span: Span,
+	sweeper: Sweeper,
+	codec: Codec,
 	bitrate_bps: u32,
 ) -> Command {
+	let (encoder, format) = codec_args(codec);
 	let mut cmd = Command::new("ffmpeg");
-	cmd.args(["-hide_banner", "-loglevel", "fatal"])
-		.args(["-re", "-threads", "1"])
-		.arg("-i")
-		.arg(input.as_ref())
-		.arg("-i")
-		.arg(sweeper.as_ref())
-		.args(["-c:a", "mp3", "-b:a", &bitrate_bps.to_string()]);
-	cmd.args([
-		"-write_xing",
-		"0",
-		"-id3v2_version",
-		"0",
-		"-map_metadata",
-		"-1",
-		"-vn",
-		"-filter_complex",
-		"[0]atrim=0:1[in];[1]adelay=1s:all=1[voice];[in][voice][0]amix=inputs=3:weights='1, 1, 0.1':dropout_transition=0.5[out]",
-		"-map",
-		"[out]",
-		"-f",
-		"mp3",
-		"-",
-	])
-	.stdout(Stdio::piped())
-	.stderr(Stdio::piped())
-	.stdin(Stdio::null());
+	cmd.args(["-hide_banner", "-loglevel", "fatal"]).args(["-re", "-threads", "1"]);
+	span.input_args(&mut cmd, input.as_ref());
+	cmd.arg("-i").arg(sweeper.path);
+	span.crossfade_input_args(&mut cmd);
+	cmd.args(["-c:a", encoder, "-b:a", &bitrate_bps.to_string()]);
+	strip_metadata_args(&mut cmd, codec);
+
+	// the gain is applied before the sweeper is mixed in
+	let mut filter = sweeper.template.map_or_else(
+		|| format!(
+			"[0]volume={}dB,asplit[main][bed];[main]atrim=0:1[in];[1]adelay=1s:all=1[voice];[in][voice][bed]amix=inputs=3:weights='1, 1, 0.1':dropout_transition=0.5",
+			span.gain
+		),
+		|template| mix_template_filter(template, span.gain, sweeper.at),
+	);
+	if let Some(crossfade) = span.crossfade {
+		filter.push_str(&format!(
+			"[mix];{};[mix][next]acrossfade=d={}[out]",
+			volume_filter("2:a", crossfade.gain, "next"),
+			crossfade.duration
+		));
+	} else {
+		filter.push_str("[out]");
+	}
+	cmd.args(["-filter_complex", &filter, "-map", "[out]", "-f", format, "-"])
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.stdin(Stdio::null());
 	cmd
 }
 
+/// the song is input 0 and the sweeper input 1. leaves the result unlabeled
+fn mix_template_filter(template: &config::MixTemplate, gain: f32, at: f32) -> String {
+	if template.position == SweeperPosition::Before {
+		// concat needs matching formats
+		const FORMAT: &str = "aresample=48000,aformat=channel_layouts=stereo";
+		return format!(
+			"[0]volume={gain}dB,{FORMAT}[main];[1]{FORMAT}[voice];[voice][main]concat=n=2:v=0:a=1"
+		);
+	}
+
+	let delay = (at.max(0.0) * 1000.0) as u64;
+	let mut filter =
+		format!("[0]volume={gain}dB[main];[1]adelay={delay}:all=1,asplit[voice][key];");
+	if template.ducking > 0.0 {
+		// the compressor pulls the song down almost completely,
+		// mixing the dry signal back in limits that to the requested depth
+		let mix = 1.0 - 10f32.powf(-template.ducking / 20.0);
+		filter.push_str(&format!(
+			"[main][key]sidechaincompress=threshold=0.003:ratio=20:attack=50:release=500:mix={mix}[ducked];"
+		));
+	} else {
+		filter.push_str("[key]anullsink;[main]anull[ducked];");
+	}
+	filter.push_str("[ducked][voice]amix=inputs=2:duration=first:normalize=0");
+	filter
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Mediainfo {
 	pub filename: PathBuf,
@@ -115,6 +256,32 @@ pub struct Mediainfo {
 	pub genre: Option<String>,
 	pub bitrate: Option<u32>,
 	pub codec: String,
+	/// in seconds
+	pub duration: Option<f64>,
+	/// integrated loudness in LUFS, if the file has replaygain tags
+	pub loudness: Option<f32>,
+	/// 0 to 5 stars
+	pub rating: Option<f32>,
+	/// set when the song goes on air
+	pub played_at: Option<chrono::DateTime<chrono::Utc>>,
+	/// what was played over the start of the song. flat, so that every value is a string
+	#[serde(flatten)]
+	pub sweeper: Option<SweeperInfo>,
+	/// the scheduled show the song was played in
+	pub show: Option<String>,
+	/// set for live sessions. the title is whatever the dj sent last
+	pub live: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SweeperInfo {
+	/// the name of the pool
+	#[serde(rename = "sweeper_pool")]
+	pub pool: String,
+	#[serde(rename = "sweeper_kind")]
+	pub kind: config::SweeperKind,
+	#[serde(rename = "sweeper")]
+	pub filename: PathBuf,
 }
 
 pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
@@ -125,7 +292,7 @@ pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
 			"-select_streams",
 			"a:0",
 			"-show_entries",
-			"format_tags:stream=codec_name,bit_rate:format=filename,bit_rate",
+			"format_tags:stream=codec_name,bit_rate:format=filename,bit_rate,duration",
 			"-of",
 			"json=c=1",
 		])
@@ -157,6 +324,7 @@ pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
 	struct PFormat {
 		filename: PathBuf,
 		bit_rate: Option<String>,
+		duration: Option<String>,
 		tags: Option<PMediainfo>,
 	}
 
@@ -178,6 +346,10 @@ pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
 		pub track: Option<String>,
 		#[serde(alias = "GENRE")]
 		pub genre: Option<String>,
+		#[serde(alias = "REPLAYGAIN_TRACK_GAIN")]
+		pub replaygain_track_gain: Option<String>,
+		#[serde(alias = "RATING")]
+		pub rating: Option<String>,
 	}
 
 	let output: P = match serde_json::from_str(&String::from_utf8_lossy(&output.stdout)) {
@@ -188,6 +360,16 @@ pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
 	};
 
 	let [stream] = output.streams;
+	let duration = output.format.duration.and_then(|x| x.parse().ok());
+	let tagged = output.format.tags.as_ref().and_then(|x| x.rating.as_deref());
+	let rating = match tagged.and_then(rating_stars) {
+		Some(x) => Some(x),
+		// ffprobe doesn't show popularimeter frames
+		None => {
+			let input = input.to_path_buf();
+			tokio::task::spawn_blocking(move || popm_rating(&input)).await.unwrap()
+		}
+	};
 	let Some(tags) = output.format.tags else {
 		return Ok(Mediainfo {
 			filename: output.format.filename.file_name().unwrap_or_default().into(),
@@ -201,6 +383,13 @@ pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
 			genre: None,
 			bitrate: stream.bit_rate.or(output.format.bit_rate).and_then(|x| x.parse().ok()),
 			codec: stream.codec_name,
+			duration,
+			loudness: None,
+			rating,
+			played_at: None,
+			sweeper: None,
+			show: None,
+			live: false,
 		});
 	};
 	Ok(Mediainfo {
@@ -215,9 +404,130 @@ pub async fn mediainfo(input: &Path) -> Result<Mediainfo, String> {
 		genre: tags.genre,
 		bitrate: stream.bit_rate.or(output.format.bit_rate).and_then(|x| x.parse().ok()),
 		codec: stream.codec_name,
+		duration,
+		loudness: tags.replaygain_track_gain.as_deref().and_then(replaygain_loudness),
+		rating,
+		played_at: None,
+		sweeper: None,
+		show: None,
+		live: false,
 	})
 }
 
+/// ratings come as stars, percent or a 0-255 popularimeter. unrated is 0
+pub fn rating_stars(tag: &str) -> Option<f32> {
+	match tag.trim().parse::<f32>().ok()? {
+		x if x <= 0.0 => None,
+		x if x <= 5.0 => Some(x),
+		x if x <= 100.0 => Some(x / 20.0),
+		x if x <= 255.0 => Some(x / 51.0),
+		_ => None,
+	}
+}
+
+/// the rating in the popularimeter frame of an id3v2 tag. blocks
+pub fn popm_rating(input: &Path) -> Option<f32> {
+	let mut file = std::io::BufReader::new(std::fs::File::open(input).ok()?);
+	let mut header = [0; 10];
+	file.read_exact(&mut header).ok()?;
+	// unsynchronised tags and extended headers are rare enough to be left alone
+	if &header[..3] != b"ID3" || header[5] & 0xC0 != 0 {
+		return None;
+	}
+	popm(header[3], &mut file, syncsafe(&header[6..10]).into()).and_then(popm_stars)
+}
+
+fn syncsafe(x: &[u8]) -> u32 {
+	x.iter().fold(0, |size, &x| size << 7 | u32::from(x & 0x7F))
+}
+
+// an email address, the rating and a play counter
+const MAX_POPM_SIZE: u64 = 1024;
+
+/// the rating byte of the first popularimeter frame in `len` bytes of id3v2.3 or 2.4 frames.
+/// the other frames are skipped, album art and all
+fn popm(version: u8, frames: &mut (impl Read + Seek), mut len: u64) -> Option<u8> {
+	let mut header = [0; 10];
+	while len >= 10 {
+		frames.read_exact(&mut header).ok()?;
+		// the rest is padding once the ids are zero
+		if header[0] == 0 {
+			return None;
+		}
+		let size = match version {
+			3 => u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+			4 => syncsafe(&header[4..8]),
+			_ => return None,
+		};
+		len = len.checked_sub(10 + u64::from(size))?;
+		if &header[..4] == b"POPM" {
+			if u64::from(size) > MAX_POPM_SIZE {
+				return None;
+			}
+			let mut data = vec![0; size as usize];
+			frames.read_exact(&mut data).ok()?;
+			let email = data.iter().position(|&x| x == 0)?;
+			return data.get(email + 1).copied();
+		}
+		frames.seek(SeekFrom::Current(size.into())).ok()?;
+	}
+	None
+}
+
+/// the byte ranges windows media player writes for its stars. unrated is 0
+const fn popm_stars(x: u8) -> Option<f32> {
+	match x {
+		0 => None,
+		1..=31 => Some(1.0),
+		32..=95 => Some(2.0),
+		96..=159 => Some(3.0),
+		160..=223 => Some(4.0),
+		224.. => Some(5.0),
+	}
+}
+
+// replaygain 2.0 gains are relative to this
+const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.0;
+
+/// turns a gain tag like "-6.20 dB" back into the loudness it was computed from
+pub fn replaygain_loudness(tag: &str) -> Option<f32> {
+	let gain: f32 = tag.trim().trim_end_matches("dB").trim().parse().ok()?;
+	Some(REPLAYGAIN_REFERENCE_LUFS - gain)
+}
+
+/// measures the integrated loudness of the whole file in LUFS. decodes everything, so it's slow
+pub async fn loudness(input: &Path) -> Result<f32, String> {
+	let child = Command::new("ffmpeg")
+		.args(["-hide_banner", "-nostats", "-threads", "1", "-i"])
+		.arg(input)
+		.args(["-vn", "-af", "loudnorm=print_format=json", "-f", "null", "-"])
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.stdin(Stdio::null())
+		.kill_on_drop(true)
+		.spawn()
+		.unwrap();
+	let output = child.wait_with_output().await.unwrap();
+	let stderr = String::from_utf8_lossy(&output.stderr);
+
+	if !output.status.success() {
+		return Err(format!("loudness analysis failed: {stderr}"));
+	}
+
+	#[derive(Deserialize)]
+	struct Loudnorm {
+		input_i: String,
+	}
+
+	// the json summary is the last thing printed
+	let json = stderr.rfind('{').map_or("", |x| &stderr[x..]);
+	serde_json::from_str::<Loudnorm>(json)
+		.ok()
+		.and_then(|x| x.input_i.parse::<f32>().ok())
+		.filter(|x| x.is_finite())
+		.ok_or_else(|| format!("loudness analysis failed for {}", input.display()))
+}
+
 /// returns true if album art was found
 /// sets length to 0 if no art is found
 pub async fn album_art_png(input: &Path) -> Result<Option<Vec<u8>>, String> {
@@ -247,3 +557,40 @@ pub async fn album_art_png(input: &Path) -> Result<Option<Vec<u8>>, String> {
 
 	Ok(Some(output.stdout))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn frame(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+		let mut frame = id.to_vec();
+		frame.extend((data.len() as u32).to_be_bytes());
+		frame.extend([0, 0]);
+		frame.extend(data);
+		frame
+	}
+
+	#[test]
+	fn popm_after_other_frames() {
+		let mut frames = frame(b"TIT2", b"\x03title");
+		frames.extend(frame(b"POPM", b"user@example.com\x00\xC4\x00\x00\x00\x01"));
+		frames.extend([0; 32]);
+		let popm = |frames: &[u8], len: usize| {
+			popm(3, &mut std::io::Cursor::new(frames), len.try_into().unwrap())
+		};
+		assert_eq!(popm(&frames, frames.len()), Some(0xC4));
+		// the tag ends in the middle of the frame
+		assert_eq!(popm(&frames, frames.len() - 40), None);
+		assert_eq!(popm(&frame(b"TIT2", b"\x03title"), 16), None);
+
+		let mut frames = frame(b"APIC", &[0; 4096]);
+		frames.extend(frame(b"POPM", &[0; 2048]));
+		assert_eq!(popm(&frames, frames.len()), None);
+	}
+
+	#[test]
+	fn popm_stars_like_windows() {
+		let stars = [0, 1, 64, 128, 196, 255].map(popm_stars);
+		assert_eq!(stars, [None, Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)]);
+	}
+}
@@ -1,3 +1,4 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
 use clap::ArgAction;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -14,11 +15,350 @@ pub struct Config {
 	pub dirs: Box<[DirectoryConfig]>,
 	pub enable_webui: bool,
 	pub shuffle: bool,
+	/// the order tracks play in without shuffle
+	#[serde(default)]
+	pub order: PlaylistOrder,
 	pub bitrate: u32,
 	pub transcode_all: bool,
-	pub sweeper_chance: f32,
+	#[serde(default)]
+	pub sweepers: Box<[SweeperPool]>,
+	#[serde(default)]
+	pub mix_templates: Box<[MixTemplate]>,
 	pub enable_mediainfo: bool,
 	pub mediainfo_history: NonZeroUsize,
+	#[serde(default = "default_outputs")]
+	pub outputs: Box<[OutputConfig]>,
+	#[serde(default)]
+	pub hls: Option<HlsConfig>,
+	/// seconds of audio sent to new listeners right away so that playback starts instantly
+	#[serde(default)]
+	pub burst_duration: u32,
+	/// how many chunks a listener can fall behind before `slow_listener` kicks in
+	#[serde(default = "default_listener_queue")]
+	pub listener_queue: NonZeroUsize,
+	#[serde(default)]
+	pub slow_listener: SlowListenerPolicy,
+	#[serde(default)]
+	pub backend: Backend,
+	/// seconds the end of a track overlaps with the start of the next one. 0 disables it
+	#[serde(default)]
+	pub crossfade: f32,
+	/// tracks are turned up or down to this integrated loudness, e.g. -16.
+	/// replaygain tags are used when present, other files get analyzed once in the background
+	/// and play as they are until then. unset disables it
+	#[serde(default)]
+	pub target_lufs: Option<f32>,
+	/// shows that replace `dirs` while they're on. the first matching slot wins
+	#[serde(default)]
+	pub schedule: Box<[ScheduleSlot]>,
+	#[serde(default)]
+	pub schedule_switch: ScheduleSwitch,
+	#[serde(default)]
+	pub live: Option<LiveConfig>,
+	/// lets listeners queue up songs. disabled if unset
+	#[serde(default)]
+	pub requests: Option<RequestConfig>,
+	/// token protected /admin api. disabled if unset
+	#[serde(default)]
+	pub admin: Option<AdminConfig>,
+	/// file that keeps tags, durations and album art locations between restarts.
+	/// only kept in memory if unset
+	#[serde(default)]
+	pub index: Option<PathBuf>,
+	/// file that keeps the shuffle rotation, the current track, statistics and the song history
+	/// between restarts. only kept in memory if unset
+	#[serde(default)]
+	pub state: Option<PathBuf>,
+	/// how long shuffle waits before a track can come up again
+	#[serde(default)]
+	pub no_repeat: NoRepeat,
+	/// keeps similar tracks apart in shuffle. relaxed step by step when nothing fits
+	#[serde(default)]
+	pub rotation_rules: Box<[RotationRule]>,
+	#[serde(default)]
+	pub weighting: Weighting,
+	/// plays whole albums in track order instead of single tracks. overrides `shuffle`
+	#[serde(default)]
+	pub albums: Option<AlbumConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistOrder {
+	#[default]
+	Path,
+	/// like `path`, but numbers count up, so "2" comes before "10"
+	Natural,
+	/// by album artist, album, disc and track. files that aren't indexed yet come first
+	Tags,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AlbumConfig {
+	#[serde(default)]
+	pub group_by: AlbumGrouping,
+	/// sweepers only play before the first track of an album
+	#[serde(default)]
+	pub sweepers_between: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumGrouping {
+	/// every directory is an album
+	#[default]
+	Directory,
+	/// by the album and album artist tags. untagged files go by directory
+	Tag,
+}
+
+/// biases shuffle on top of the directory weights
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Weighting {
+	/// how much ratings count. 1 makes 5 stars 5 times as likely as 1 star, 0 ignores them.
+	/// read from rating tags or the popularimeter frame of id3v2.3 and 2.4 tags
+	#[serde(default)]
+	pub rating: f32,
+	/// extra weight of a file that was just added. 2 makes it 3 times as likely
+	#[serde(default)]
+	pub new_boost: f32,
+	/// days over which `new_boost` fades out, going by the file's mtime
+	#[serde(default)]
+	pub new_days: f32,
+}
+
+/// limits that are 0 don't apply
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RotationRule {
+	pub tag: RotationTag,
+	/// no track with the same tag within this many minutes
+	#[serde(default)]
+	pub minutes: f32,
+	/// no track with the same tag within this many tracks
+	#[serde(default)]
+	pub tracks: usize,
+	/// at most this many tracks with the same tag in a row
+	#[serde(default)]
+	pub in_a_row: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationTag {
+	Artist,
+	Album,
+	Genre,
+}
+
+/// a track has to be older than both limits to be picked again
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NoRepeat {
+	/// not within the last this many tracks
+	#[serde(default)]
+	pub tracks: usize,
+	/// not within the last this many hours
+	#[serde(default)]
+	pub hours: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminConfig {
+	pub tokens: Box<[AdminToken]>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminToken {
+	/// who gets logged for the actions done with this token
+	pub name: String,
+	pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RequestConfig {
+	pub max_queue_len: NonZeroUsize,
+	/// seconds a client has to wait between two requests
+	pub client_cooldown: u32,
+	/// seconds after a song was played before it can be requested again
+	pub replay_cooldown: u32,
+}
+
+/// lets icecast source clients (BUTT, ffmpeg) take over from the playlist
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LiveConfig {
+	/// sources stream without a content length, so they get their own port
+	pub port: u16,
+	/// the mount sources connect to, e.g. "/live"
+	pub mount: String,
+	pub password: String,
+	/// seconds to wait for the source to reconnect before the playlist resumes
+	#[serde(default = "default_grace_period")]
+	pub grace_period: u32,
+}
+
+const fn default_grace_period() -> u32 {
+	10
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduleSlot {
+	pub name: String,
+	/// every day if empty
+	#[serde(default)]
+	pub days: Box<[Weekday]>,
+	/// local time. a slot that ends before it starts runs past midnight
+	pub start: NaiveTime,
+	pub end: NaiveTime,
+	pub dirs: Box<[DirectoryConfig]>,
+	/// album mode for this show, `Config::albums` doesn't carry over
+	#[serde(default)]
+	pub albums: Option<AlbumConfig>,
+}
+
+impl ScheduleSlot {
+	pub fn contains(&self, now: NaiveDateTime) -> bool {
+		let on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+		let (day, time) = (now.weekday(), now.time());
+		if self.start < self.end {
+			on(day) && (self.start..self.end).contains(&time)
+		} else {
+			// the part after midnight belongs to the day the slot started on
+			(on(day) && time >= self.start) || (on(day.pred()) && time < self.end)
+		}
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleSwitch {
+	/// let the current song finish
+	#[default]
+	Track,
+	/// interrupt the current song when the slot starts
+	Cut,
+}
+
+/// a directory of sweepers of one kind.
+/// before each track every pool rolls its chance, and one of the pools that hit is picked by weight
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SweeperPool {
+	pub name: String,
+	pub kind: SweeperKind,
+	pub dir: PathBuf,
+	pub chance: f32,
+	#[serde(default = "default_weight")]
+	pub weight: f32,
+	/// name of a mix template. the classic liner mix is used without one
+	#[serde(default)]
+	pub template: Option<String>,
+}
+
+/// how a sweeper is mixed into the song
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MixTemplate {
+	pub name: String,
+	pub position: SweeperPosition,
+	/// seconds after the start of the song for `intro`, before the end for `outro`
+	#[serde(default)]
+	pub offset: f32,
+	/// how far the song is pushed down under the voice, in dB. 0 disables ducking
+	#[serde(default)]
+	pub ducking: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SweeperPosition {
+	/// over the start of the song
+	Intro,
+	/// played on its own right before the song
+	Before,
+	/// over the end of the song
+	Outro,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SweeperKind {
+	StationId,
+	Liner,
+	Promo,
+}
+
+const fn default_weight() -> f32 {
+	1.0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+	#[default]
+	Ffmpeg,
+	/// reads tags in-process and sends mp3 files as they are, without decoding them.
+	/// there's no encoder, so other formats, transcoding, gain, crossfade and sweepers still
+	/// need ffmpeg. without it, configs that use them are rejected.
+	/// requires the `passthrough` feature
+	Passthrough,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowListenerPolicy {
+	/// skip what the listener missed
+	#[default]
+	Resync,
+	Disconnect,
+}
+
+const fn default_listener_queue() -> NonZeroUsize {
+	NonZeroUsize::new(4).unwrap()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HlsConfig {
+	/// mount of the output to cut into segments. can't be opus
+	pub output: String,
+	/// in seconds
+	pub segment_duration: NonZeroU32,
+	/// how many segments are kept and listed in the playlist
+	pub segments: NonZeroUsize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OutputConfig {
+	pub mount: String,
+	pub codec: Codec,
+	/// overrides the global bitrate. files are always transcoded if this is set
+	#[serde(default)]
+	pub bitrate: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+	Mp3,
+	/// opus in ogg
+	Opus,
+	/// aac in adts
+	Aac,
+}
+
+impl Codec {
+	pub const fn content_type(self) -> &'static str {
+		match self {
+			Self::Mp3 => "audio/mpeg",
+			Self::Opus => "audio/ogg",
+			Self::Aac => "audio/aac",
+		}
+	}
+
+	/// whether a file in `source_codec` can be sent without transcoding
+	pub fn can_copy(self, source_codec: &str) -> bool {
+		self == Self::Mp3 && source_codec == "mp3"
+	}
+}
+
+fn default_outputs() -> Box<[OutputConfig]> {
+	Box::new([OutputConfig { mount: "/stream".to_string(), codec: Codec::Mp3, bitrate: None }])
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -124,10 +464,29 @@ Optionally pass a path to the config file to be created/read (not directory).",
 		value_name = "0..1",
 		long,
 		action,
-		help = format!("123"),
+		help = "Chance to play a sweeper from --sweeper-dir over the start of a song.",
 		default_value_t = ZeroOneF32(0.0)
 	)]
 	pub sweeper_chance: ZeroOneF32,
+	#[clap(
+		long,
+		help = "The directory to pick sweepers from.
+Note: --use-config allows to specify multiple sweeper pools.",
+		default_value = LEGACY_SWEEPER_DIR
+	)]
+	pub sweeper_dir: PathBuf,
+	#[clap(
+		long,
+		value_name = "FILE",
+		help = "Where to keep the library index. Makes track changes fast on slow disks."
+	)]
+	pub index: Option<PathBuf>,
+	#[clap(
+		long,
+		value_name = "FILE",
+		help = "Where to keep the shuffle rotation, playback position, statistics and song history so that they survive restarts."
+	)]
+	pub state: Option<PathBuf>,
 	#[clap(
 		long = "bitrate",
 		help = "The bitrate to use for transcoding. Plain value for bps and suffixed with 'k' for kbps.",
@@ -216,11 +575,13 @@ impl From<CliConfig> for Config {
 					Some(DirectoryConfig {
 						root: cli.root.clone(),
 						mode: DirectoryConfigMode::Include(include.into_boxed_slice()),
+						weight: 1.0,
 					})
 				} else if !exclude.is_empty() {
 					Some(DirectoryConfig {
 						root: cli.root.clone(),
 						mode: DirectoryConfigMode::Exclude(exclude.into_boxed_slice()),
+						weight: 1.0,
 					})
 				} else {
 					// unreachable!()
@@ -230,6 +591,7 @@ impl From<CliConfig> for Config {
 			None => Some(DirectoryConfig {
 				root: cli.root,
 				mode: DirectoryConfigMode::Exclude([].into()),
+				weight: 1.0,
 			}),
 		};
 
@@ -243,11 +605,42 @@ impl From<CliConfig> for Config {
 			dirs: dir.into_boxed_slice(),
 			enable_webui: cli.enable_webui,
 			shuffle: cli.shuffle,
-			sweeper_chance: cli.sweeper_chance.0,
+			order: PlaylistOrder::default(),
+			sweepers: (cli.sweeper_chance.0 > 0.0)
+				.then(|| SweeperPool {
+					name: "sweepers".to_string(),
+					kind: SweeperKind::Liner,
+					dir: cli.sweeper_dir,
+					chance: cli.sweeper_chance.0,
+					weight: default_weight(),
+					template: None,
+				})
+				.into_iter()
+				.collect(),
+			mix_templates: Box::new([]),
 			bitrate: cli.transcode_bitrate.bits_per_second.get(),
 			transcode_all: cli.transcode_all,
 			enable_mediainfo: cli.enable_mediainfo,
 			mediainfo_history: cli.mediainfo_history,
+			outputs: default_outputs(),
+			hls: None,
+			burst_duration: 0,
+			listener_queue: default_listener_queue(),
+			slow_listener: SlowListenerPolicy::default(),
+			backend: Backend::default(),
+			crossfade: 0.0,
+			target_lufs: None,
+			schedule: Box::new([]),
+			schedule_switch: ScheduleSwitch::default(),
+			live: None,
+			requests: None,
+			admin: None,
+			index: cli.index,
+			state: cli.state,
+			no_repeat: NoRepeat::default(),
+			rotation_rules: Box::new([]),
+			weighting: Weighting::default(),
+			albums: None,
 		}
 	}
 }
@@ -260,19 +653,41 @@ impl Default for Config {
 			dirs: Box::new([DirectoryConfig {
 				root: PathBuf::from("./"),
 				mode: DirectoryConfigMode::Exclude([].into()),
+				weight: 1.0,
 			}]),
 			shuffle: true,
-			sweeper_chance: 0.0,
+			order: PlaylistOrder::default(),
+			sweepers: Box::new([]),
+			mix_templates: Box::new([]),
 			enable_webui: true,
 			bitrate: 128_000,
 			transcode_all: false,
 			enable_mediainfo: true,
 			mediainfo_history: NonZeroUsize::new(16).unwrap(),
+			outputs: default_outputs(),
+			hls: None,
+			burst_duration: 0,
+			listener_queue: default_listener_queue(),
+			slow_listener: SlowListenerPolicy::default(),
+			backend: Backend::default(),
+			crossfade: 0.0,
+			target_lufs: None,
+			schedule: Box::new([]),
+			schedule_switch: ScheduleSwitch::default(),
+			live: None,
+			requests: None,
+			admin: None,
+			index: None,
+			state: None,
+			no_repeat: NoRepeat::default(),
+			rotation_rules: Box::new([]),
+			weighting: Weighting::default(),
+			albums: None,
 		}
 	}
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "mode", content = "paths")]
 pub enum DirectoryConfigMode {
@@ -280,10 +695,13 @@ pub enum DirectoryConfigMode {
 	Exclude(Box<[PathBuf]>),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct DirectoryConfig {
 	pub root: PathBuf,
 	pub mode: DirectoryConfigMode,
+	/// how often tracks from here come up in shuffle compared to other directories
+	#[serde(default = "default_weight")]
+	pub weight: f32,
 }
 
 pub enum Error {
@@ -307,11 +725,50 @@ pub fn generate_or_load(path: &Path) -> Result<Config, Error> {
 	if !path.exists() {
 		generate_config_file(path)
 	} else {
-		let x = std::fs::read_to_string(path).unwrap();
-		Ok(toml::from_str(&x)?)
+		load(path)
 	}
 }
 
+pub fn load(path: &Path) -> Result<Config, Error> {
+	let x = std::fs::read_to_string(path)?;
+	let mut config = toml::from_str(&x)?;
+	migrate(&mut config, toml::from_str(&x)?);
+	Ok(config)
+}
+
+/// keys that older config files have
+#[derive(Deserialize)]
+struct LegacyConfig {
+	/// chance of a sweeper from ./sweepers, before there were pools
+	sweeper_chance: Option<f32>,
+}
+
+fn migrate(config: &mut Config, legacy: LegacyConfig) {
+	if let Some(chance) = legacy.sweeper_chance {
+		if !config.sweepers.is_empty() {
+			println!("Ignoring 'sweeper_chance', the [[sweepers]] pools replace it.");
+			return;
+		}
+		println!(
+			"'sweeper_chance' is deprecated, it's read as a sweeper pool for {LEGACY_SWEEPER_DIR}. \
+			Replace it with a [[sweepers]] pool."
+		);
+		if chance > 0.0 {
+			config.sweepers = Box::new([SweeperPool {
+				name: "sweepers".to_string(),
+				kind: SweeperKind::Liner,
+				dir: LEGACY_SWEEPER_DIR.into(),
+				chance,
+				weight: default_weight(),
+				template: None,
+			}]);
+		}
+	}
+}
+
+// where sweepers were before there were pools, still the default of --sweeper-dir
+const LEGACY_SWEEPER_DIR: &str = "./sweepers";
+
 pub fn generate_config_file(path: &Path) -> Result<Config, Error> {
 	if !path.exists() {
 		path.parent()
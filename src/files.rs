@@ -1,6 +1,9 @@
 use std::{
+	cmp::Ordering,
+	iter::Peekable,
 	path::{Path, PathBuf},
-	sync::Arc,
+	str::Chars,
+	sync::{Arc, Mutex, PoisonError},
 };
 
 use rayon::{prelude::*, ThreadPool};
@@ -9,6 +12,29 @@ use crate::config::{self, DirectoryConfig};
 
 const SUPPORTED_FORMATS: [&str; 4] = ["mp3", "flac", "opus", "wav"];
 
+/// files of every sweeper pool, in the same order. pools that can play need at least one
+pub fn collect_sweepers(pools: &[config::SweeperPool]) -> Result<Vec<Vec<PathBuf>>, String> {
+	pools
+		.iter()
+		.map(|pool| {
+			let files = collect(&[DirectoryConfig {
+				mode: config::DirectoryConfigMode::Exclude(vec![].into_boxed_slice()),
+				root: pool.dir.clone(),
+				weight: 1.0,
+			}]);
+			if pool.chance > 0.0 && files.is_empty() {
+				return Err(format!(
+					"Sweeper pool {:?} has a chance of {}, but no sweepers found in {}",
+					pool.name,
+					pool.chance,
+					pool.dir.display()
+				));
+			}
+			Ok(files)
+		})
+		.collect()
+}
+
 pub fn collect(path: &[config::DirectoryConfig]) -> Vec<PathBuf> {
 	let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
 
@@ -16,29 +42,152 @@ pub fn collect(path: &[config::DirectoryConfig]) -> Vec<PathBuf> {
 }
 
 fn walk(path: DirectoryConfig, pool: Arc<ThreadPool>) -> Vec<PathBuf> {
-	jwalk::WalkDir::new(path.root)
+	jwalk::WalkDir::new(&path.root)
 		.parallelism(jwalk::Parallelism::RayonExistingPool { pool, busy_timeout: None })
 		.into_iter()
 		.par_bridge()
 		.flatten()
-		.filter(|x| match &path.mode {
-			config::DirectoryConfigMode::Exclude(dirs) => {
-				if dirs.is_empty() {
-					return true;
-				}
-				dirs.iter().any(|y| !x.path().starts_with(y))
-			}
-			config::DirectoryConfigMode::Include(dirs) => {
-				dirs.iter().any(|y| x.path().starts_with(y))
-			}
-		})
+		.filter(|x| allowed(&path, &x.path()))
 		.flat_map(|x| {
-			let cond = x.file_type.is_file()
-				&& Path::new(&x.file_name)
-					.extension()
-					.and_then(|x| x.to_str())
-					.is_some_and(|x| SUPPORTED_FORMATS.contains(&x));
+			let cond = x.file_type.is_file() && is_supported(Path::new(&x.file_name));
 			cond.then(|| x.path())
 		})
 		.collect::<Vec<_>>()
 }
+
+/// whether `collect` would pick up the file
+pub fn matches(dirs: &[DirectoryConfig], path: &Path) -> bool {
+	is_supported(path) && directory(dirs, path).is_some()
+}
+
+/// the first of `dirs` the file belongs to
+pub fn directory<'a>(dirs: &'a [DirectoryConfig], path: &Path) -> Option<&'a DirectoryConfig> {
+	dirs.iter().find(|x| path.starts_with(&x.root) && allowed(x, path))
+}
+
+fn allowed(dir: &DirectoryConfig, path: &Path) -> bool {
+	match &dir.mode {
+		config::DirectoryConfigMode::Exclude(dirs) => {
+			if dirs.is_empty() {
+				return true;
+			}
+			dirs.iter().any(|y| !path.starts_with(y))
+		}
+		config::DirectoryConfigMode::Include(dirs) => dirs.iter().any(|y| path.starts_with(y)),
+	}
+}
+
+fn is_supported(path: &Path) -> bool {
+	path.extension().and_then(|x| x.to_str()).is_some_and(|x| SUPPORTED_FORMATS.contains(&x))
+}
+
+/// compares paths a component at a time, with runs of digits compared by their value
+pub fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+	let (mut x, mut y) = (a.components(), b.components());
+	loop {
+		match (x.next(), y.next()) {
+			(Some(x), Some(y)) => {
+				let x = x.as_os_str().to_string_lossy();
+				let y = y.as_os_str().to_string_lossy();
+				match natural_cmp_str(&mut x.chars().peekable(), &mut y.chars().peekable()) {
+					Ordering::Equal => {}
+					x => return x,
+				}
+			}
+			// "01" and "1" are the same number, the order still has to be the same every time
+			(None, None) => return a.cmp(b),
+			(x, y) => return x.is_some().cmp(&y.is_some()),
+		}
+	}
+}
+
+fn natural_cmp_str(a: &mut Peekable<Chars>, b: &mut Peekable<Chars>) -> Ordering {
+	let number = |x: &mut Peekable<Chars>| {
+		let mut number = String::new();
+		while let Some(c) = x.next_if(char::is_ascii_digit) {
+			number.push(c);
+		}
+		number
+	};
+	loop {
+		let ordering = match (a.peek(), b.peek()) {
+			(Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+				let (x, y) = (number(a), number(b));
+				let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+				// no overflow for long numbers, more digits is bigger
+				x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+			}
+			(Some(_), Some(_)) => a.next().cmp(&b.next()),
+			(x, y) => return x.is_some().cmp(&y.is_some()),
+		};
+		if ordering != Ordering::Equal {
+			return ordering;
+		}
+	}
+}
+
+/// replaces the file in one go so that a crash mid-write doesn't leave a truncated file behind
+pub fn write(path: &Path, data: &[u8]) -> std::io::Result<()> {
+	// two saves of the same file would share the temporary one
+	static WRITING: Mutex<()> = Mutex::new(());
+	let _writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+	if let Some(x) = path.parent() {
+		std::fs::create_dir_all(x)?;
+	}
+	// "radio.state" and "radio.index" can't end up with the same one
+	let mut tmp = path.as_os_str().to_owned();
+	tmp.push(format!(".{}.tmp", std::process::id()));
+	std::fs::write(&tmp, data)?;
+	std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sorted(paths: &[&str]) -> Vec<String> {
+		let mut paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+		paths.sort_by(|a, b| natural_cmp(a, b));
+		paths.iter().map(|x| x.to_string_lossy().into_owned()).collect()
+	}
+
+	#[test]
+	fn write_same_stem() {
+		let dir = std::env::temp_dir().join(format!("radio-write-{}", std::process::id()));
+		let (state, index) = (dir.join("radio.state"), dir.join("radio.index"));
+		std::thread::scope(|x| {
+			x.spawn(|| write(&state, b"state").unwrap());
+			x.spawn(|| write(&index, b"index").unwrap());
+		});
+		assert_eq!(std::fs::read(&state).unwrap(), b"state");
+		assert_eq!(std::fs::read(&index).unwrap(), b"index");
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn leading_zeros() {
+		assert_eq!(sorted(&["1.mp3", "01.mp3", "002.mp3"]), ["01.mp3", "1.mp3", "002.mp3"]);
+		assert_eq!(sorted(&["01.mp3", "1.mp3"]), sorted(&["1.mp3", "01.mp3"]));
+	}
+
+	#[test]
+	fn mixed_digit_runs() {
+		assert_eq!(
+			sorted(&["a10b2.mp3", "a2b10.mp3", "a2b2.mp3", "a10b10.mp3", "b1.mp3"]),
+			["a2b2.mp3", "a2b10.mp3", "a10b2.mp3", "a10b10.mp3", "b1.mp3"]
+		);
+		assert_eq!(
+			sorted(&["track 100000000000000000000001", "track 99999999999999999999999"]),
+			["track 99999999999999999999999", "track 100000000000000000000001"]
+		);
+	}
+
+	#[test]
+	fn unequal_component_counts() {
+		assert_eq!(
+			sorted(&["music/2/a.mp3", "music/10.mp3", "music/2.mp3", "music"]),
+			["music", "music/2/a.mp3", "music/2.mp3", "music/10.mp3"]
+		);
+	}
+}
@@ -0,0 +1,150 @@
+use std::{collections::VecDeque, fmt::Write, time::Duration};
+
+use axum::body::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+	audio,
+	config::{self, Codec},
+};
+
+pub struct Segment {
+	pub sequence: u64,
+	pub data: Bytes,
+	pub duration: Duration,
+	pub start: DateTime<Utc>,
+}
+
+/// cuts the output of one mount into segments and keeps the last few of them around
+pub struct Hls {
+	codec: Codec,
+	segment_duration: Duration,
+	window: usize,
+	segments: std::sync::RwLock<VecDeque<Segment>>,
+}
+
+impl Hls {
+	pub fn new(config: &config::HlsConfig, codec: Codec) -> Self {
+		Self {
+			codec,
+			segment_duration: Duration::from_secs(config.segment_duration.get().into()),
+			window: config.segments.get(),
+			segments: Default::default(),
+		}
+	}
+
+	pub const fn codec(&self) -> Codec {
+		self.codec
+	}
+
+	pub const fn extension(&self) -> &'static str {
+		match self.codec {
+			Codec::Mp3 => "mp3",
+			Codec::Aac => "aac",
+			// rejected when the config is checked, raw opus can't be packed into segments
+			Codec::Opus => "opus",
+		}
+	}
+
+	pub async fn run(&self, mut rx: broadcast::Receiver<audio::Chunk>) {
+		let mut sequence = 0;
+		let mut buf = Vec::new();
+		let mut duration = Duration::ZERO;
+		let mut start = Utc::now();
+		// media time of the start of the current segment
+		let mut timestamp = Duration::ZERO;
+		loop {
+			let chunk = match rx.recv().await {
+				Ok(x) => x,
+				Err(RecvError::Lagged(_)) => continue,
+				Err(RecvError::Closed) => break,
+			};
+
+			// segments never get longer than the target duration
+			if !buf.is_empty() && duration + chunk.duration > self.segment_duration {
+				let mut data = id3_timestamp(timestamp);
+				data.append(&mut buf);
+				let segment = Segment { sequence, data: data.into(), duration, start };
+				{
+					let mut segments = self.segments.write().unwrap();
+					if segments.len() == self.window {
+						segments.pop_front();
+					}
+					segments.push_back(segment);
+				}
+				sequence += 1;
+				timestamp += duration;
+				duration = Duration::ZERO;
+			}
+
+			if buf.is_empty() {
+				start = Utc::now();
+			}
+			// chunks end on frame boundaries, so segments do too
+			buf.extend_from_slice(&chunk.data);
+			duration += chunk.duration;
+		}
+	}
+
+	pub fn segment(&self, sequence: u64) -> Option<Bytes> {
+		self.segments
+			.read()
+			.unwrap()
+			.iter()
+			.find(|x| x.sequence == sequence)
+			.map(|x| x.data.clone())
+	}
+
+	/// none until the first segment is done
+	#[allow(clippy::significant_drop_tightening)]
+	pub fn playlist(&self) -> Option<String> {
+		let segments = self.segments.read().unwrap();
+		if segments.is_empty() {
+			return None;
+		}
+		// has to stay the same for the whole stream
+		let target_duration = self.segment_duration.as_secs_f64().ceil() as u64;
+
+		let mut playlist = String::new();
+		writeln!(playlist, "#EXTM3U").unwrap();
+		writeln!(playlist, "#EXT-X-VERSION:3").unwrap();
+		writeln!(playlist, "#EXT-X-TARGETDURATION:{target_duration}").unwrap();
+		writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{}", segments.front().map_or(0, |x| x.sequence))
+			.unwrap();
+		for x in segments.iter() {
+			writeln!(
+				playlist,
+				"#EXT-X-PROGRAM-DATE-TIME:{}",
+				x.start.to_rfc3339_opts(SecondsFormat::Millis, true)
+			)
+			.unwrap();
+			writeln!(playlist, "#EXTINF:{:.3},", x.duration.as_secs_f64()).unwrap();
+			writeln!(playlist, "{}.{}", x.sequence, self.extension()).unwrap();
+		}
+		Some(playlist)
+	}
+}
+
+/// the id3 tag packed audio segments start with, so that players can line them up.
+/// the timestamp is a 33 bit mpeg-ts timestamp of 90 kHz ticks
+fn id3_timestamp(timestamp: Duration) -> Vec<u8> {
+	const OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+	let ticks = (timestamp.as_micros() * 9 / 100) as u64 & ((1 << 33) - 1);
+	let frame_size = OWNER.len() + 8;
+	let mut tag = Vec::with_capacity(20 + frame_size);
+	// id3v2.4 header, the sizes are syncsafe integers
+	tag.extend_from_slice(b"ID3\x04\x00\x00");
+	tag.extend_from_slice(&syncsafe(10 + frame_size));
+	tag.extend_from_slice(b"PRIV");
+	tag.extend_from_slice(&syncsafe(frame_size));
+	tag.extend_from_slice(&[0, 0]);
+	tag.extend_from_slice(OWNER);
+	tag.extend_from_slice(&ticks.to_be_bytes());
+	tag
+}
+
+/// 7 bits per byte
+const fn syncsafe(x: usize) -> [u8; 4] {
+	[(x >> 21) as u8 & 0x7f, (x >> 14) as u8 & 0x7f, (x >> 7) as u8 & 0x7f, x as u8 & 0x7f]
+}
@@ -0,0 +1,114 @@
+use std::{
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use axum::body::Bytes;
+use futures_core::Stream;
+use tokio::sync::watch;
+
+use crate::{cmd, player::Player};
+
+/// amount of audio bytes between two metadata blocks
+pub const METAINT: usize = 16000;
+
+// the length byte counts 16 byte blocks
+const MAX_METADATA_LEN: usize = 255 * 16;
+
+/// wraps a listener's stream and inserts shoutcast metadata blocks every `METAINT` bytes
+pub struct IcyStream<S> {
+	stream: S,
+	player: Player,
+	next_song_rx: watch::Receiver<()>,
+	until_metadata: usize,
+	// the title hasn't been sent since the song changed
+	dirty: bool,
+}
+
+impl<S> IcyStream<S> {
+	pub fn new(stream: S, player: Player) -> Self {
+		Self {
+			stream,
+			next_song_rx: player.subscribe_next_song(),
+			player,
+			until_metadata: METAINT,
+			dirty: true,
+		}
+	}
+
+	fn interleave(&mut self, mut data: Bytes) -> Bytes {
+		let mut out = Vec::with_capacity(data.len() + 1);
+		while data.len() >= self.until_metadata {
+			out.extend_from_slice(&data.split_to(self.until_metadata));
+			self.write_metadata(&mut out);
+			self.until_metadata = METAINT;
+		}
+		self.until_metadata -= data.len();
+		out.extend_from_slice(&data);
+		out.into()
+	}
+
+	fn write_metadata(&mut self, out: &mut Vec<u8>) {
+		if self.next_song_rx.has_changed().unwrap_or(false) {
+			self.next_song_rx.borrow_and_update();
+			self.dirty = true;
+		}
+
+		// the lock is only held for writing while the history is updated.
+		// if we miss it, the title goes out with the next block
+		let title = if self.dirty {
+			self.player.try_read_mediainfo(|x| x.first().map(stream_title)).flatten()
+		} else {
+			None
+		};
+
+		let Some(title) = title else {
+			// empty metadata block, clients keep showing the last title
+			out.push(0);
+			return;
+		};
+		self.dirty = false;
+
+		let mut metadata = format!("StreamTitle='{title}';");
+		if metadata.len() > MAX_METADATA_LEN {
+			let mut end = MAX_METADATA_LEN - 2;
+			while !metadata.is_char_boundary(end) {
+				end -= 1;
+			}
+			metadata.truncate(end);
+			metadata.push_str("';");
+		}
+		let blocks = metadata.len().div_ceil(16);
+		out.push(blocks as u8);
+		out.extend_from_slice(metadata.as_bytes());
+		out.resize(out.len() + blocks * 16 - metadata.len(), 0);
+	}
+}
+
+impl<S, E> Stream for IcyStream<S>
+where
+	S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+	type Item = Result<Bytes, E>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		match Pin::new(&mut this.stream).poll_next(cx) {
+			Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(this.interleave(data)))),
+			x => x,
+		}
+	}
+}
+
+/// same preference order as the webapp uses
+fn stream_title(mediainfo: &cmd::Mediainfo) -> String {
+	let title = mediainfo
+		.title
+		.clone()
+		.or_else(|| mediainfo.publisher.clone())
+		.unwrap_or_else(|| mediainfo.filename.to_string_lossy().into_owned());
+	match mediainfo.album_artist.as_ref().or(mediainfo.artist.as_ref()) {
+		Some(artist) => format!("{artist} - {title}"),
+		None => title,
+	}
+}
@@ -0,0 +1,142 @@
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		RwLock,
+	},
+	time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{cmd, files};
+
+// bumped when probing finds out more, older entries are probed again
+const VERSION: u32 = 1;
+
+/// what is known about a file. stale once its mtime or size change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+	#[serde(default)]
+	version: u32,
+	mtime: SystemTime,
+	size: u64,
+	pub mediainfo: cmd::Mediainfo,
+	/// an image next to the file, for when it has no album art of its own
+	pub cover: Option<PathBuf>,
+	/// measured loudness of files without replaygain tags
+	#[serde(default)]
+	pub loudness: Option<f32>,
+}
+
+impl Entry {
+	pub fn new(
+		path: &Path,
+		mediainfo: cmd::Mediainfo,
+		cover: Option<PathBuf>,
+	) -> std::io::Result<Self> {
+		let metadata = std::fs::metadata(path)?;
+		Ok(Self {
+			version: VERSION,
+			mtime: metadata.modified()?,
+			size: metadata.len(),
+			mediainfo,
+			cover,
+			loudness: None,
+		})
+	}
+
+	pub const fn mtime(&self) -> SystemTime {
+		self.mtime
+	}
+
+	fn is_fresh(&self, metadata: &std::fs::Metadata) -> bool {
+		self.version == VERSION
+			&& metadata.len() == self.size
+			&& metadata.modified().is_ok_and(|x| x == self.mtime)
+	}
+}
+
+/// tags, durations and album art locations of the library so that a track change
+/// only has to stat the file instead of probing it. saved as json
+pub struct Index {
+	path: Option<PathBuf>,
+	entries: RwLock<HashMap<PathBuf, Entry>>,
+	// changed since the last save
+	dirty: AtomicBool,
+}
+
+impl Index {
+	/// starts out empty if the file is missing or broken
+	pub fn load(path: Option<PathBuf>) -> Self {
+		let entries: HashMap<PathBuf, Entry> = path
+			.as_deref()
+			.and_then(|path| match std::fs::read(path) {
+				Ok(x) => serde_json::from_slice(&x)
+					.map_err(|e| println!("Ignoring broken index {}: {e}", path.display()))
+					.ok(),
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+				Err(e) => {
+					println!("Could not read index {}: {e}", path.display());
+					None
+				}
+			})
+			.unwrap_or_default();
+		let outdated = entries.values().filter(|x| x.version != VERSION).count();
+		if outdated > 0 {
+			println!(
+				"{outdated} files in the index are from an older version and get probed again"
+			);
+		}
+		Self { path, entries: RwLock::new(entries), dirty: AtomicBool::new(false) }
+	}
+
+	/// the entry if the file didn't change since it was indexed
+	pub fn get(&self, path: &Path) -> Option<Entry> {
+		let metadata = std::fs::metadata(path).ok()?;
+		self.entries.read().unwrap().get(path).filter(|x| x.is_fresh(&metadata)).cloned()
+	}
+
+	pub fn insert(&self, path: PathBuf, entry: Entry) {
+		// json can't store it
+		if path.to_str().is_none() {
+			return;
+		}
+		self.entries.write().unwrap().insert(path, entry);
+		self.dirty.store(true, Ordering::Relaxed);
+	}
+
+	pub fn set_loudness(&self, path: &Path, loudness: f32) {
+		if let Some(x) = self.entries.write().unwrap().get_mut(path) {
+			x.loudness = Some(loudness);
+			self.dirty.store(true, Ordering::Relaxed);
+		}
+	}
+
+	/// forgets files that are not in the library anymore
+	pub fn retain(&self, mut f: impl FnMut(&Path) -> bool) {
+		let mut entries = self.entries.write().unwrap();
+		let len = entries.len();
+		entries.retain(|x, _| f(x));
+		if entries.len() != len {
+			self.dirty.store(true, Ordering::Relaxed);
+		}
+	}
+
+	pub fn read<R>(&self, f: impl FnOnce(&HashMap<PathBuf, Entry>) -> R) -> R {
+		f(&self.entries.read().unwrap())
+	}
+
+	/// writes the index if anything changed. blocks
+	pub fn save(&self) -> std::io::Result<()> {
+		let Some(path) = &self.path else {
+			return Ok(());
+		};
+		if !self.dirty.swap(false, Ordering::Relaxed) {
+			return Ok(());
+		}
+		let data = serde_json::to_vec(&*self.entries.read().unwrap())?;
+		files::write(path, &data)
+	}
+}
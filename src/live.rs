@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use axum::body::Bytes;
+use base64::Engine;
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+	sync::broadcast::{self, error::RecvError},
+};
+
+use crate::{
+	audio::{AudioReader, Data},
+	cmd,
+	config::{self, Codec},
+	player::Player,
+};
+
+// request heads bigger than this are not from a source client
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// accepts icecast sources: a SOURCE or PUT request with basic auth,
+/// followed by raw audio until the connection closes.
+/// metadata updates come in as `GET /admin/metadata` on the same port
+pub async fn run(player: Player, host: String, config: config::LiveConfig) -> std::io::Result<()> {
+	let listener = TcpListener::bind((host, config.port)).await?;
+	println!("Listening for live sources on port {}", config.port);
+	loop {
+		let (stream, address) = listener.accept().await?;
+		let player = player.clone();
+		let config = config.clone();
+		tokio::spawn(async move {
+			if let Err(e) = handle(player, &config, stream).await {
+				println!("live source {address}: {e}");
+			}
+		});
+	}
+}
+
+struct Request {
+	method: String,
+	path: String,
+	query: String,
+	headers: HashMap<String, String>,
+}
+
+async fn read_head(stream: &mut TcpStream) -> Result<(Request, Vec<u8>), String> {
+	let mut buf = Vec::with_capacity(1024);
+	loop {
+		if stream.read_buf(&mut buf).await.map_err(|x| x.to_string())? == 0 {
+			return Err("connection closed".to_string());
+		}
+		let mut headers = [httparse::EMPTY_HEADER; 32];
+		let mut request = httparse::Request::new(&mut headers);
+		match request.parse(&buf) {
+			Ok(httparse::Status::Complete(len)) => {
+				let path = request.path.unwrap_or_default();
+				let (path, query) = path.split_once('?').unwrap_or((path, ""));
+				let head = Request {
+					method: request.method.unwrap_or_default().to_string(),
+					path: path.to_string(),
+					query: query.to_string(),
+					headers: request
+						.headers
+						.iter()
+						.map(|x| {
+							(
+								x.name.to_ascii_lowercase(),
+								String::from_utf8_lossy(x.value).into_owned(),
+							)
+						})
+						.collect(),
+				};
+				// whatever came after the head is already audio
+				return Ok((head, buf.split_off(len)));
+			}
+			Ok(httparse::Status::Partial) if buf.len() < MAX_HEAD_LEN => continue,
+			Ok(httparse::Status::Partial) => return Err("request head too long".to_string()),
+			Err(e) => return Err(e.to_string()),
+		}
+	}
+}
+
+fn authorized(request: &Request, password: &str) -> bool {
+	// the user is "source" by convention, only the password matters
+	request
+		.headers
+		.get("authorization")
+		.and_then(|x| x.strip_prefix("Basic "))
+		.and_then(|x| base64::engine::general_purpose::STANDARD.decode(x.trim()).ok())
+		.and_then(|x| String::from_utf8(x).ok())
+		.is_some_and(|x| {
+			x.split_once(':').is_some_and(|(_, x)| {
+				constant_time_eq::constant_time_eq(x.as_bytes(), password.as_bytes())
+			})
+		})
+}
+
+async fn respond(stream: &mut TcpStream, response: &str) -> Result<(), String> {
+	stream.write_all(response.as_bytes()).await.map_err(|x| x.to_string())
+}
+
+async fn handle(
+	player: Player,
+	config: &config::LiveConfig,
+	mut stream: TcpStream,
+) -> Result<(), String> {
+	let (request, leftover) = read_head(&mut stream).await?;
+
+	if !authorized(&request, &config.password) {
+		respond(
+			&mut stream,
+			"HTTP/1.0 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"Icecast2 Server\"\r\n\r\n",
+		)
+		.await?;
+		return Err("wrong password".to_string());
+	}
+
+	match (request.method.as_str(), request.path.as_str()) {
+		("GET", "/admin/metadata") => {
+			let query = form_urlencoded::parse(request.query.as_bytes()).collect::<HashMap<_, _>>();
+			if query.get("mode").is_some_and(|x| x == "updinfo") {
+				if let Some(song) = query.get("song") {
+					player.set_live_title(song.to_string()).await;
+				}
+			}
+			respond(
+				&mut stream,
+				"HTTP/1.0 200 OK\r\nContent-Type: text/xml\r\n\r\n<?xml version=\"1.0\"?>\n<iceresponse><message>Metadata update successful</message><return>1</return></iceresponse>\n",
+			)
+			.await
+		}
+		("SOURCE" | "PUT", path) if path == config.mount => {
+			let Some(tx) = player.start_live(live_mediainfo(&request, &config.mount)) else {
+				respond(&mut stream, "HTTP/1.0 403 Mountpoint in use\r\n\r\n").await?;
+				return Err("another source is live".to_string());
+			};
+			println!("live source connected to {}", config.mount);
+
+			let result = receive(&mut stream, &request, leftover, &tx).await;
+			drop(tx);
+			player.end_live();
+			println!("live source disconnected from {}", config.mount);
+			result
+		}
+		_ => respond(&mut stream, "HTTP/1.0 404 Not Found\r\n\r\n").await,
+	}
+}
+
+async fn receive(
+	stream: &mut TcpStream,
+	request: &Request,
+	leftover: Vec<u8>,
+	tx: &broadcast::Sender<Bytes>,
+) -> Result<(), String> {
+	// ffmpeg waits for this before it sends any audio
+	if request.headers.get("expect").is_some_and(|x| x.eq_ignore_ascii_case("100-continue")) {
+		respond(stream, "HTTP/1.1 100 Continue\r\n\r\n").await?;
+	} else {
+		respond(stream, "HTTP/1.0 200 OK\r\n\r\n").await?;
+	}
+
+	if !leftover.is_empty() {
+		let _ = tx.send(leftover.into());
+	}
+	let mut buf = vec![0; 4096];
+	loop {
+		match stream.read(&mut buf).await {
+			Ok(0) => return Ok(()),
+			Ok(read) => {
+				let _ = tx.send(Bytes::copy_from_slice(&buf[..read]));
+			}
+			Err(e) => return Err(e.to_string()),
+		}
+	}
+}
+
+fn live_mediainfo(request: &Request, mount: &str) -> cmd::Mediainfo {
+	let header = |name: &str| request.headers.get(name).filter(|x| !x.is_empty()).cloned();
+	let codec = match header("content-type").as_deref() {
+		Some("audio/mpeg") => "mp3",
+		Some("audio/ogg" | "application/ogg") => "ogg",
+		Some("audio/aac" | "audio/aacp") => "aac",
+		_ => "unknown",
+	};
+	cmd::Mediainfo {
+		filename: mount.trim_start_matches('/').into(),
+		title: header("ice-description"),
+		publisher: header("ice-name"),
+		genre: header("ice-genre"),
+		codec: codec.to_string(),
+		live: true,
+		..Default::default()
+	}
+}
+
+/// transcodes the live source for one output
+pub struct LiveAudioReader {
+	metadata: cmd::Mediainfo,
+	// killed on drop
+	_handle: tokio::process::Child,
+	stdout: tokio::process::ChildStdout,
+}
+
+impl LiveAudioReader {
+	pub fn start(
+		mut rx: broadcast::Receiver<Bytes>,
+		codec: Codec,
+		bitrate: u32,
+		metadata: cmd::Mediainfo,
+	) -> Self {
+		let mut handle = cmd::spawn_live_ffmpeg(codec, bitrate);
+		let mut stdin = handle.stdin.take().unwrap();
+		let stdout = handle.stdout.take().unwrap();
+		// closing stdin when the source goes away ends the stream
+		tokio::spawn(async move {
+			loop {
+				match rx.recv().await {
+					Ok(data) => {
+						if stdin.write_all(&data).await.is_err() {
+							break;
+						}
+					}
+					Err(RecvError::Lagged(_)) => continue,
+					Err(RecvError::Closed) => break,
+				}
+			}
+		});
+		Self { metadata, _handle: handle, stdout }
+	}
+}
+
+#[async_trait::async_trait]
+impl AudioReader for LiveAudioReader {
+	async fn read_data(&mut self, buf: &mut [u8]) -> Result<Data, std::io::Error> {
+		self.stdout.read(buf).await.map(Data::Audio)
+	}
+
+	async fn read_metadata(&mut self) -> Result<cmd::Mediainfo, String> {
+		Ok(self.metadata.clone())
+	}
+}
@@ -3,73 +3,146 @@
 #![deny(clippy::semicolon_if_nothing_returned)]
 #![allow(unused)]
 
+mod album;
 mod audio;
 mod cmd;
 mod config;
 mod files;
+mod hls;
+mod icy;
+mod index;
+mod live;
+#[cfg(feature = "passthrough")]
+mod passthrough;
 mod player;
+mod reload;
+mod requests;
+mod rotation;
+mod state;
+mod watch;
 
 use axum::{
 	body::Body,
 	debug_handler,
 	extract::{
 		ws::{self, rejection::WebSocketUpgradeRejection},
-		Path, Query, State, WebSocketUpgrade,
+		ConnectInfo, Path, Query, State, WebSocketUpgrade,
 	},
 	http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
 	response::{Html, IntoResponse, Redirect},
-	routing::get,
+	routing::{get, post},
 	Router,
 };
 use clap::Parser;
 
 use player::Player;
-use std::{fmt::Write, sync::Arc, time::Duration};
+use std::{
+	fmt::Write,
+	future::IntoFuture,
+	net::SocketAddr,
+	path::PathBuf,
+	sync::{atomic::Ordering, Arc},
+	time::Duration,
+};
 use tokio::time::Interval;
 
-use crate::config::DirectoryConfig;
-
 #[tokio::main]
 async fn main() {
-	match cmd::check_executables() {
-		(true, _) => {}
-		(false, missing) => {
+	// i dont want to see this code
+	// into a function it goes
+	let Some((config, config_path)) = config_shit() else {
+		return;
+	};
+
+	if config.backend == config::Backend::Passthrough {
+		if cfg!(not(feature = "passthrough")) {
 			println!(
-				"Could not execute: {}",
-				missing.into_iter().map(|x| format!("{:?}", x.0)).collect::<Vec<_>>().join(", ")
+				"The passthrough backend needs {} to be built with the 'passthrough' feature.",
+				env!("CARGO_PKG_NAME")
 			);
-			let x = std::env::current_dir()
-				.map(|x| format!(" ({:?})", x.display()))
-				.unwrap_or_default();
-			println!("Make sure ffmpeg is installed and accessible. Or just put those two in the current directory{x}.");
-
 			return;
 		}
+		if !cmd::has_ffmpeg() {
+			println!("ffmpeg not found. Only mp3 files can be played, as they are, and there will be no album art.");
+		}
+	} else {
+		match cmd::check_executables() {
+			(true, _) => {}
+			(false, missing) => {
+				println!(
+					"Could not execute: {}",
+					missing
+						.into_iter()
+						.map(|x| format!("{:?}", x.0))
+						.collect::<Vec<_>>()
+						.join(", ")
+				);
+				let x = std::env::current_dir()
+					.map(|x| format!(" ({:?})", x.display()))
+					.unwrap_or_default();
+				println!("Make sure ffmpeg is installed and accessible. Or just put those two in the current directory{x}.");
+
+				return;
+			}
+		}
 	}
 
-	// i dont want to see this code
-	// into a function it goes
-	let Some(config) = config_shit() else {
-		return;
+	let sweeper_files = match files::collect_sweepers(&config.sweepers) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("{}", e);
+			return;
+		}
 	};
 
-	let sweeper_list = files::collect(&[DirectoryConfig {
-		mode: config::DirectoryConfigMode::Exclude(vec![].into_boxed_slice()),
-		root: cmd::SWEEPER_DIR.into(),
-	}]);
+	let port = config.port;
 
-	if config.sweeper_chance > 0.0 && sweeper_list.is_empty() {
-		println!(
-			"Sweeper chance is set to {}, but no sweepers found in {}",
-			config.sweeper_chance,
-			cmd::SWEEPER_DIR
-		);
+	if let Some(x) = config.outputs.iter().find(|x| !x.mount.starts_with('/')) {
+		println!("Output mount {:?} must start with '/'", x.mount);
 		return;
 	}
+	if let Some((_, x)) = config
+		.outputs
+		.iter()
+		.enumerate()
+		.find(|(i, x)| config.outputs[..*i].iter().any(|y| y.mount == x.mount))
+	{
+		println!("Output mount {:?} is used by more than one output", x.mount);
+		return;
+	}
+	if let Some(x) = config.outputs.iter().find(|x| is_builtin_route(&x.mount)) {
+		println!("Output mount {:?} is taken by a built-in route", x.mount);
+		return;
+	}
+	if let Some(live) = &config.live {
+		if !live.mount.starts_with('/') {
+			println!("Live mount {:?} must start with '/'", live.mount);
+			return;
+		}
+		if config.outputs.iter().any(|x| x.mount == live.mount) {
+			println!("Live mount {:?} is also an output mount", live.mount);
+			return;
+		}
+		if !cmd::has_ffmpeg() {
+			println!("Live sources need ffmpeg.");
+			return;
+		}
+	}
+	if let Some(hls) = &config.hls {
+		match config.outputs.iter().find(|x| x.mount == hls.output) {
+			Some(x) if x.codec == config::Codec::Opus => {
+				println!("HLS output {:?} can't be opus, use mp3 or aac", hls.output);
+				return;
+			}
+			Some(_) => {}
+			None => {
+				println!("HLS output {:?} is not an output mount", hls.output);
+				return;
+			}
+		}
+	}
 
-	let port = config.port;
-
-	let player = match Player::new(files::collect(&config.dirs), sweeper_list, config.clone()) {
+	let player = match Player::new(sweeper_files, config.clone()) {
 		Ok(player) => player,
 		Err(e) => {
 			println!("Player error: {:?}", e);
@@ -79,11 +152,40 @@ async fn main() {
 
 	println!("Playlist:");
 	let take = 10;
-	for x in player.files().iter().take(take) {
+	let library = player.library();
+	for x in library.playlist.iter().take(take) {
 		println!("  {}", x.display());
 	}
-	if player.files().len() > take {
-		println!(" ... and {} more", player.files().len() - take);
+	if library.playlist.len() > take {
+		println!(" ... and {} more", library.playlist.len() - take);
+	}
+
+	tokio::spawn({
+		let player = player.clone();
+		async move {
+			if let Err(e) = watch::run(player).await {
+				println!("Not watching the library for changes: {}", e);
+			}
+		}
+	});
+
+	if let Some(path) = config_path {
+		let player = player.clone();
+		tokio::spawn(async move {
+			if let Err(e) = reload::run(player, path).await {
+				println!("Not watching the config file for changes: {}", e);
+			}
+		});
+	}
+
+	if let Some(live) = config.live.clone() {
+		let player = player.clone();
+		let host = config.host.clone();
+		tokio::spawn(async move {
+			if let Err(e) = live::run(player, host, live).await {
+				println!("Live source listener stopped: {}", e);
+			}
+		});
 	}
 
 	let app = define_routes(Router::new(), &config)
@@ -93,10 +195,43 @@ async fn main() {
 	let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await.unwrap();
 	println!("Listening on port {}", port);
 
-	axum::serve(listener, app).await.unwrap();
+	let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>());
+	tokio::select! {
+		x = server.into_future() => x.unwrap(),
+		() = shutdown_signal() => {
+			println!("Shutting down");
+			player.shutdown().await;
+		}
+	}
+}
+
+/// ctrl-c, or SIGTERM from service managers
+async fn shutdown_signal() {
+	let interrupt = async {
+		if tokio::signal::ctrl_c().await.is_err() {
+			std::future::pending::<()>().await;
+		}
+	};
+	#[cfg(unix)]
+	let terminate = async {
+		use tokio::signal::unix::{signal, SignalKind};
+		match signal(SignalKind::terminate()) {
+			Ok(mut x) => {
+				x.recv().await;
+			}
+			Err(_) => std::future::pending().await,
+		}
+	};
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+	tokio::select! {
+		() = interrupt => {}
+		() = terminate => {}
+	}
 }
 
-fn config_shit() -> Option<Arc<config::Config>> {
+/// the config and the file it came from
+fn config_shit() -> Option<(Arc<config::Config>, Option<PathBuf>)> {
 	let pre_config = config::PreCliConfig::try_parse();
 	let mut use_config = None;
 	if let Ok(config::PreCliConfig { generate_config, use_config: use_config_arg, .. }) = pre_config
@@ -118,14 +253,14 @@ fn config_shit() -> Option<Arc<config::Config>> {
 		use_config = use_config_arg;
 	}
 
-	let config: Arc<config::Config> = if let Some(use_config) = use_config {
+	let (config, path) = if let Some(use_config) = use_config {
 		let path = match use_config {
 			config::UseConfigArg::Custom(path) => path,
 			config::UseConfigArg::Default => config::config_path(),
 		};
 		println!("Loading config from {}", path.display());
 		match config::generate_or_load(&path) {
-			Ok(x) => Arc::new(x),
+			Ok(x) => (Arc::new(x), Some(path)),
 			Err(error) => {
 				match error {
 					config::Error::Io(e) => println!("Could not generate or load config: {}", e),
@@ -135,17 +270,32 @@ fn config_shit() -> Option<Arc<config::Config>> {
 			}
 		}
 	} else {
-		Arc::new(config::CliConfig::parse().into())
+		(Arc::new(config::CliConfig::parse().into()), None)
 	};
 
-	Some(config)
+	Some((config, path))
+}
+
+/// whether a mount would overlap with one of the routes from `define_routes`
+fn is_builtin_route(mount: &str) -> bool {
+	const ROUTES: [&str; 6] =
+		["/album_art", "/mediainfo", "/mediainfo/ws", "/webui", "/requests", "/requests/search"];
+	const PREFIXES: [&str; 2] = ["/hls/", "/admin/"];
+	// an empty mount is the webpage at "/"
+	let mount = mount.trim_end_matches('/');
+	mount.is_empty()
+		|| ROUTES.contains(&mount)
+		|| PREFIXES.iter().any(|x| mount.starts_with(x) || x.trim_end_matches('/') == mount)
 }
 
 fn define_routes(r: Router<Player>, config: &Arc<config::Config>) -> Router<Player> {
-	let mut r = r
-		.route("/stream", get(stream))
-		.route("/", get(webpage))
-		.route("/album_art", get(album_art));
+	let mut r = r.route("/", get(webpage)).route("/album_art", get(album_art));
+	for (output, x) in config.outputs.iter().enumerate() {
+		r = r.route(
+			&x.mount,
+			get(move |player, request_headers| stream(player, request_headers, output)),
+		);
+	}
 	#[cfg(feature = "webapp")]
 	{
 		r = r.route("/*file", get(webpage_assets));
@@ -157,6 +307,17 @@ fn define_routes(r: Router<Player>, config: &Arc<config::Config>) -> Router<Play
 	if config.enable_webui {
 		r = r.route("/webui", get(webui));
 	}
+	if config.hls.is_some() {
+		r = r.route("/hls/stream.m3u8", get(hls_playlist));
+		r = r.route("/hls/:segment", get(hls_segment));
+	}
+	if config.requests.is_some() {
+		r = r.route("/requests", get(request_queue).post(request));
+		r = r.route("/requests/search", get(request_search));
+	}
+	if config.admin.is_some() {
+		r = r.route("/admin/:action", post(admin));
+	}
 	r
 }
 
@@ -193,26 +354,79 @@ async fn webpage_assets(Path(path): Path<String>) -> impl IntoResponse {
 	}
 }
 
-#[debug_handler]
-async fn stream(State(player): State<Player>) -> Result<impl IntoResponse, String> {
-	let stream = player.subscribe();
+async fn stream(
+	State(player): State<Player>,
+	request_headers: HeaderMap,
+	output: usize,
+) -> Result<impl IntoResponse, String> {
+	let stream = player.subscribe(output);
+	let codec = player.outputs()[output].config.codec;
+	// shoutcast metadata doesn't mix with ogg pages
+	let icy_metadata = codec != config::Codec::Opus
+		&& request_headers.get("icy-metadata").is_some_and(|x| x == "1");
 
 	let mut headers = axum::http::HeaderMap::new();
-	headers.insert("Content-Type", "audio/mpeg".parse().unwrap());
+	headers.insert("Content-Type", codec.content_type().parse().unwrap());
 	headers.insert(
 		"Cache-Control",
 		"no-store, no-cache, must-revalidate, s-max-age=0".parse().unwrap(),
 	);
+	let bitrate = player.outputs()[output].config.bitrate.or_else(|| {
+		let config = player.config();
+		config.transcode_all.then_some(config.bitrate)
+	});
 	headers.insert(
 		"x-bitrate",
-		if player.config().transcode_all {
-			player.config().bitrate.to_string().parse().unwrap()
-		} else {
-			"vary".parse().unwrap()
-		},
+		bitrate.map_or_else(|| "vary".parse().unwrap(), |x| x.to_string().parse().unwrap()),
 	);
 
-	Ok((headers, Body::from_stream(stream).into_response()))
+	let body = if icy_metadata {
+		headers.insert("icy-metaint", icy::METAINT.into());
+		Body::from_stream(icy::IcyStream::new(stream, player))
+	} else {
+		Body::from_stream(stream)
+	};
+
+	Ok((headers, body.into_response()))
+}
+
+async fn hls_playlist(State(player): State<Player>) -> impl IntoResponse {
+	let Some(playlist) = player.hls().unwrap().playlist() else {
+		// a playlist needs at least one segment
+		return StatusCode::SERVICE_UNAVAILABLE.into_response();
+	};
+	(
+		[
+			(header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+			(header::CACHE_CONTROL, "no-cache"),
+		],
+		playlist,
+	)
+		.into_response()
+}
+
+async fn hls_segment(
+	State(player): State<Player>,
+	Path(segment): Path<String>,
+) -> impl IntoResponse {
+	let hls = player.hls().unwrap();
+	let data = segment
+		.split_once('.')
+		.filter(|(_, extension)| *extension == hls.extension())
+		.and_then(|(sequence, _)| sequence.parse().ok())
+		.and_then(|sequence| hls.segment(sequence));
+
+	data.map_or_else(
+		|| StatusCode::NOT_FOUND.into_response(),
+		|data| {
+			let headers = [
+				(header::CONTENT_TYPE, hls.codec().content_type()),
+				// segments never change
+				(header::CACHE_CONTROL, "max-age=3600"),
+			];
+			(headers, data).into_response()
+		},
+	)
 }
 
 async fn mediainfo(State(player): State<Player>) -> impl IntoResponse {
@@ -239,6 +453,7 @@ async fn mediainfo_ws(
 		})
 		.on_upgrade(move |mut socket| async move {
 			let mut rx = player.subscribe_next_song();
+			let mut queue_rx = player.subscribe_queue();
 			let mut interaval = tokio::time::interval(Duration::from_secs(19));
 			loop {
 				tokio::select! {
@@ -252,11 +467,146 @@ async fn mediainfo_ws(
 						// println!("sending new song to socket");
 						let _ = socket.send(ws::Message::Text("next".to_string())).await;
 					},
+					_ = queue_rx.changed() => {
+						let queue = request_queue_json(&player);
+						let _ = socket.send(ws::Message::Text(format!("{{\"queue\":{queue}}}"))).await;
+					},
 				}
 			}
 		}))
 }
 
+fn request_queue_json(player: &Player) -> String {
+	player.read_requests(|x| serde_json::to_string(x).unwrap()).unwrap()
+}
+
+async fn request_queue(State(player): State<Player>) -> impl IntoResponse {
+	([(header::CONTENT_TYPE, "application/json")], request_queue_json(&player))
+}
+
+#[derive(serde::Deserialize)]
+struct RequestQuery {
+	id: usize,
+}
+
+async fn request(
+	State(player): State<Player>,
+	ConnectInfo(address): ConnectInfo<SocketAddr>,
+	Query(query): Query<RequestQuery>,
+) -> impl IntoResponse {
+	match player.request(query.id, address.ip()).unwrap() {
+		Ok(()) => (StatusCode::OK, request_queue_json(&player)).into_response(),
+		Err(e @ requests::Rejection::NoSuchTrack) => {
+			(StatusCode::NOT_FOUND, e.to_string()).into_response()
+		}
+		Err(e @ requests::Rejection::TooSoon(_)) => {
+			(StatusCode::TOO_MANY_REQUESTS, e.to_string()).into_response()
+		}
+		Err(e) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+	}
+}
+
+#[derive(serde::Deserialize)]
+struct SearchQuery {
+	q: String,
+}
+
+async fn request_search(
+	State(player): State<Player>,
+	Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+	#[derive(serde::Serialize)]
+	struct SearchResult<'a> {
+		id: usize,
+		filename: &'a std::ffi::OsStr,
+	}
+
+	let results = player.search(&query.q, 50);
+	let results = results
+		.iter()
+		.map(|(id, path)| SearchResult { id: *id, filename: path.file_name().unwrap_or_default() })
+		.collect::<Vec<_>>();
+	([(header::CONTENT_TYPE, "application/json")], serde_json::to_string(&results).unwrap())
+}
+
+#[derive(serde::Deserialize)]
+struct AdminQuery {
+	id: Option<usize>,
+	enabled: Option<bool>,
+}
+
+async fn admin(
+	State(player): State<Player>,
+	ConnectInfo(address): ConnectInfo<SocketAddr>,
+	headers: HeaderMap,
+	Path(action): Path<String>,
+	Query(query): Query<AdminQuery>,
+) -> impl IntoResponse {
+	let token = headers
+		.get(header::AUTHORIZATION)
+		.and_then(|x| x.to_str().ok())
+		.and_then(|x| x.strip_prefix("Bearer "));
+	let config = player.config();
+	let Some(name) = token.and_then(|token| {
+		let admin = config.admin.as_ref()?;
+		// every token is compared so that the time doesn't tell which one was close
+		admin
+			.tokens
+			.iter()
+			.fold(None, |found, x| {
+				let eq = constant_time_eq::constant_time_eq(x.token.as_bytes(), token.as_bytes());
+				found.or_else(|| eq.then_some(x))
+			})
+			.map(|x| x.name.clone())
+	}) else {
+		println!("admin: rejected {action:?} from {address}");
+		return (StatusCode::UNAUTHORIZED, "unauthorized".to_string());
+	};
+
+	let result = match (action.as_str(), query.id, query.enabled) {
+		("skip", ..) => {
+			player.skip();
+			Ok("skipped".to_string())
+		}
+		("pause", ..) => {
+			player.pause();
+			Ok("paused".to_string())
+		}
+		("resume", ..) => {
+			player.resume();
+			Ok("resumed".to_string())
+		}
+		("jump", Some(id), _) => {
+			if player.jump(id) {
+				Ok(format!("jumped to {}", id))
+			} else {
+				Err((StatusCode::NOT_FOUND, "no such track".to_string()))
+			}
+		}
+		("shuffle", _, Some(enabled)) => {
+			player.set_shuffle(enabled);
+			Ok(format!("shuffle {}", if enabled { "on" } else { "off" }))
+		}
+		("rescan", ..) => match player.rescan().await {
+			Ok(x) => Ok(format!("rescanned, {x} files")),
+			Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("rescan failed: {e:?}"))),
+		},
+		("jump" | "shuffle", ..) => Err((StatusCode::BAD_REQUEST, "missing parameter".to_string())),
+		_ => Err((StatusCode::NOT_FOUND, "unknown action".to_string())),
+	};
+
+	match result {
+		Ok(x) => {
+			println!("admin: {name} ({address}): {x}");
+			(StatusCode::OK, x)
+		}
+		Err((status, x)) => {
+			println!("admin: {name} ({address}): {action} failed: {x}");
+			(status, x)
+		}
+	}
+}
+
 async fn webui(State(player): State<Player>) -> impl IntoResponse {
 	fn display_bytes(x: usize) -> String {
 		match x {
@@ -288,6 +638,30 @@ async fn webui(State(player): State<Player>) -> impl IntoResponse {
 		writeln!(&mut body, "Copied: {}", display_bytes(stats.bytes_copied)).unwrap();
 		writeln!(&mut body, "Target bandwidth: {}/s", display_bytes(stats.target_badwidth))
 			.unwrap();
+		let slow = player.slow_listeners();
+		let resynced = slow.resynced.load(Ordering::Relaxed);
+		writeln!(&mut body, "Listeners that fell behind and were resynced: {resynced}").unwrap();
+		let disconnected = slow.disconnected.load(Ordering::Relaxed);
+		writeln!(&mut body, "Listeners disconnected for falling behind: {disconnected}").unwrap();
+		let library = player.library_statistics();
+		writeln!(
+			&mut body,
+			"Library: {} tracks, {} indexed ({})",
+			library.tracks,
+			library.indexed,
+			display_time(library.duration)
+		)
+		.unwrap();
+		for (output, mount) in player.outputs().iter().zip(stats.mounts.iter()) {
+			writeln!(
+				&mut body,
+				"{}: {} listeners, sent {}",
+				output.config.mount,
+				mount.listeners,
+				display_bytes(mount.bytes_sent)
+			)
+			.unwrap();
+		}
 		body
 	};
 	([(header::CONTENT_TYPE, "text/plain")], body)
@@ -0,0 +1,210 @@
+use std::{
+	fs::File,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use symphonia::core::{
+	codecs::CODEC_TYPE_NULL,
+	errors::Error as SymphoniaError,
+	formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+	io::MediaSourceStream,
+	meta::{MetadataOptions, StandardTagKey, Tag},
+	probe::Hint,
+	units::Time,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+	audio::{AudioReader, Data},
+	cmd,
+};
+
+fn probe(input: &Path) -> Result<symphonia::core::probe::ProbeResult, String> {
+	let file = File::open(input).map_err(|x| x.to_string())?;
+	let stream = MediaSourceStream::new(Box::new(file), Default::default());
+	let mut hint = Hint::new();
+	if let Some(extension) = input.extension().and_then(|x| x.to_str()) {
+		hint.with_extension(extension);
+	}
+	symphonia::default::get_probe()
+		.format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+		.map_err(|x| format!("probe failed for {}: {}", input.display(), x))
+}
+
+/// same as `cmd::mediainfo`, without ffprobe. blocks
+pub fn mediainfo(input: &Path) -> Result<cmd::Mediainfo, String> {
+	let mut probed = probe(input)?;
+
+	// tags in front of the container (id3) and inside of it (vorbis comments) are merged
+	let mut tags: Vec<Tag> = probed
+		.metadata
+		.get()
+		.and_then(|x| x.current().map(|x| x.tags().to_vec()))
+		.unwrap_or_default();
+	if let Some(x) = probed.format.metadata().current() {
+		tags.extend_from_slice(x.tags());
+	}
+	let tag = |key: StandardTagKey| {
+		tags.iter().find(|x| x.std_key == Some(key)).map(|x| x.value.to_string())
+	};
+
+	let track = probed
+		.format
+		.tracks()
+		.iter()
+		.find(|x| x.codec_params.codec != CODEC_TYPE_NULL)
+		.ok_or_else(|| format!("no audio track in {}", input.display()))?;
+	let params = &track.codec_params;
+	let codec = symphonia::default::get_codecs()
+		.get_codec(params.codec)
+		.map_or("unknown", |x| x.short_name)
+		.to_string();
+	let duration = params
+		.time_base
+		.zip(params.n_frames)
+		.map(|(time_base, frames)| to_duration(time_base.calc_time(frames)).as_secs_f64());
+	let bitrate = duration.and_then(|seconds| {
+		let size = std::fs::metadata(input).ok()?.len() as f64;
+		(seconds > 0.0).then(|| (size * 8.0 / seconds) as u32)
+	});
+
+	Ok(cmd::Mediainfo {
+		filename: input.file_name().unwrap_or_default().into(),
+		title: tag(StandardTagKey::TrackTitle),
+		album: tag(StandardTagKey::Album),
+		artist: tag(StandardTagKey::Artist),
+		album_artist: tag(StandardTagKey::AlbumArtist),
+		publisher: tag(StandardTagKey::Label),
+		disc: tag(StandardTagKey::DiscNumber),
+		track: tag(StandardTagKey::TrackNumber),
+		genre: tag(StandardTagKey::Genre),
+		bitrate,
+		codec,
+		duration,
+		loudness: tag(StandardTagKey::ReplayGainTrackGain)
+			.as_deref()
+			.and_then(cmd::replaygain_loudness),
+		rating: tag(StandardTagKey::Rating)
+			.as_deref()
+			.and_then(cmd::rating_stars)
+			.or_else(|| cmd::popm_rating(input)),
+		played_at: None,
+		sweeper: None,
+		show: None,
+		live: false,
+	})
+}
+
+fn to_duration(time: Time) -> Duration {
+	Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+}
+
+type Packet = Result<(Vec<u8>, Duration), String>;
+
+/// sends the frames of an mp3 file between `start` and `end` as they are, paced in real time
+/// like `ffmpeg -re`. there's no encoder, so anything that needs transcoding goes through ffmpeg
+pub struct PassthroughReader {
+	file: PathBuf,
+	metadata: Option<cmd::Mediainfo>,
+	// dropping this stops the demuxer thread
+	rx: mpsc::Receiver<Packet>,
+	pending: Vec<u8>,
+	start: tokio::time::Instant,
+	played: Duration,
+}
+
+impl PassthroughReader {
+	pub fn start(input: impl AsRef<Path>, start: f32, end: Option<f32>) -> Self {
+		let file = input.as_ref().to_path_buf();
+		let (tx, rx) = mpsc::channel(8);
+		tokio::task::spawn_blocking({
+			let file = file.clone();
+			let start = Duration::from_secs_f32(start.max(0.0));
+			let end = end.map(|x| Duration::from_secs_f32(x.max(0.0)));
+			move || demux(&file, start, end, &tx)
+		});
+		Self {
+			file,
+			metadata: None,
+			rx,
+			pending: Vec::new(),
+			start: tokio::time::Instant::now(),
+			played: Duration::ZERO,
+		}
+	}
+}
+
+fn demux(input: &Path, start: Duration, end: Option<Duration>, tx: &mpsc::Sender<Packet>) {
+	let mut format = match probe(input) {
+		Ok(x) => x.format,
+		Err(e) => {
+			let _ = tx.blocking_send(Err(e));
+			return;
+		}
+	};
+	let time_base = format.default_track().and_then(|x| x.codec_params.time_base);
+	if !start.is_zero() {
+		// lands on or before `start`, the frames up to it are skipped below
+		let time = Time::from(start.as_secs_f64());
+		let _ = format.seek(SeekMode::Coarse, SeekTo::Time { time, track_id: None });
+	}
+	loop {
+		let packet = match format.next_packet() {
+			Ok(x) => x,
+			Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+				break
+			}
+			Err(e) => {
+				let _ = tx.blocking_send(Err(e.to_string()));
+				break;
+			}
+		};
+		let time = |x| time_base.map_or(Duration::ZERO, |base| to_duration(base.calc_time(x)));
+		let (at, duration) = (time(packet.ts), time(packet.dur));
+		if end.is_some_and(|end| at >= end) {
+			break;
+		}
+		if at + duration <= start {
+			continue;
+		}
+		if tx.blocking_send(Ok((packet.data.into_vec(), duration))).is_err() {
+			break;
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl AudioReader for PassthroughReader {
+	async fn read_data(&mut self, buf: &mut [u8]) -> Result<Data, std::io::Error> {
+		while self.pending.is_empty() {
+			match self.rx.recv().await {
+				None => return Ok(Data::Audio(0)),
+				Some(Err(e)) => return Ok(Data::Error(e)),
+				Some(Ok((data, duration))) => {
+					tokio::time::sleep_until(self.start + self.played).await;
+					self.played += duration;
+					self.pending = data;
+				}
+			}
+		}
+		let read = self.pending.len().min(buf.len());
+		buf[..read].copy_from_slice(&self.pending[..read]);
+		self.pending.drain(..read);
+		Ok(Data::Audio(read))
+	}
+
+	async fn read_metadata(&mut self) -> Result<cmd::Mediainfo, String> {
+		match self.metadata {
+			Some(ref x) => Ok(x.clone()),
+			None => {
+				let file = self.file.clone();
+				let metadata = tokio::task::spawn_blocking(move || mediainfo(&file))
+					.await
+					.map_err(|x| x.to_string())??;
+				self.metadata = Some(metadata.clone());
+				Ok(metadata)
+			}
+		}
+	}
+}
@@ -1,9 +1,10 @@
 use std::{
-	collections::VecDeque,
+	collections::{HashMap, HashSet, VecDeque},
+	ops::Range,
 	path::{Path, PathBuf},
 	pin::Pin,
 	sync::{
-		atomic::{AtomicUsize, Ordering},
+		atomic::{AtomicBool, AtomicUsize, Ordering},
 		Arc,
 	},
 	time::Duration,
@@ -11,15 +12,20 @@ use std::{
 
 use axum::body::Bytes;
 use futures_core::Stream;
-use rand::{seq::IteratorRandom, Rng};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
 use tokio::{
-	sync::{oneshot, RwLock},
+	sync::{broadcast, oneshot, RwLock},
 	task::JoinSet,
 };
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 
+#[cfg(feature = "passthrough")]
+use crate::passthrough;
 use crate::{
+	album,
 	audio::{self, AudioReader, FFMpegAudioReader},
-	cmd, config,
+	cmd, config, files, hls, index, live, requests, rotation, state,
 };
 
 #[derive(Clone)]
@@ -43,6 +49,16 @@ impl<T> FixedDeque<T> {
 		x.make_contiguous();
 	}
 
+	/// takes the most recent one out
+	pub fn pop(&mut self) -> Option<T> {
+		self.0.pop_front()
+	}
+
+	pub fn resize(&mut self, size: usize) {
+		self.0.truncate(size);
+		self.1 = size;
+	}
+
 	pub fn as_slice(&self) -> &[T] {
 		let (a, b) = self.0.as_slices();
 		assert!(b.is_empty());
@@ -77,28 +93,309 @@ impl<T: Stream + Unpin> futures_core::Stream for TrackDropStream<T> {
 	}
 }
 
-#[derive(Debug, Default)]
+/// all-time numbers, kept in the state file. the ones about right now start over
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Statistics {
 	pub time_played: Duration,
+	#[serde(skip)]
 	pub listeners: usize,
 	pub max_listeners: usize,
 	pub bytes_transcoded: usize,
 	pub bytes_copied: usize,
 	pub bytes_sent: usize,
+	#[serde(skip)]
 	pub target_badwidth: usize,
+	/// same order as the outputs in the config
+	pub mounts: Box<[MountStatistics]>,
+}
+
+/// listeners that fell behind the queue. counted by the streams themselves, so no lock
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SlowListeners {
+	/// each listener counts once, no matter how often it was resynced
+	pub resynced: AtomicUsize,
+	pub disconnected: AtomicUsize,
+}
+
+impl Clone for SlowListeners {
+	fn clone(&self) -> Self {
+		Self {
+			resynced: self.resynced.load(Ordering::Relaxed).into(),
+			disconnected: self.disconnected.load(Ordering::Relaxed).into(),
+		}
+	}
+}
+
+pub struct LibraryStatistics {
+	pub tracks: usize,
+	pub indexed: usize,
+	/// of the indexed tracks
+	pub duration: Duration,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MountStatistics {
+	#[serde(skip)]
+	pub listeners: usize,
+	pub bytes_sent: usize,
 }
 
 pub struct Inner {
-	playlist: Box<[PathBuf]>,
-	sweeper_list: Box<[PathBuf]>,
+	// replaced as a whole on rescans
+	library: std::sync::RwLock<Arc<Library>>,
+	shuffle: AtomicBool,
+	// both replaced on config reloads
+	sweepers: std::sync::RwLock<Arc<[SweeperPool]>>,
 	album_art: RwLock<AlbumImage>,
 	index: AtomicUsize,
+	// where the regular order goes on from. requests play in between without moving it
+	cursor: AtomicUsize,
+	// the track the last one faded into and how many seconds of it were already played
+	faded_into: std::sync::Mutex<Option<(usize, f32)>>,
+	// the track picked up from the state, it was counted before the restart
+	resumed: std::sync::Mutex<Option<PathBuf>>,
+	// the track on air, where it started in the file and since when it's been running
+	playing: std::sync::Mutex<Option<(PathBuf, f32, Option<tokio::time::Instant>)>>,
+	live: std::sync::Mutex<Option<LiveSession>>,
+	rotation: std::sync::Mutex<rotation::Rotation>,
+	requests: Option<std::sync::Mutex<requests::Requests>>,
+	queue_tx: tokio::sync::watch::Sender<()>,
 	mediainfo: RwLock<FixedDeque<cmd::Mediainfo>>,
-	tx: tokio::sync::broadcast::Sender<Bytes>,
+	// files the loudness analysis failed on, there's no point in decoding them again
+	loudness_failed: std::sync::Mutex<HashSet<PathBuf>>,
+	metadata: index::Index,
+	// the background scan filling the index
+	scan: std::sync::Mutex<Option<tokio::task::AbortHandle>>,
+	outputs: Box<[Output]>,
+	hls: Option<hls::Hls>,
 	next_song_tx: tokio::sync::watch::Sender<()>,
 	task_control_tx: tokio::sync::watch::Sender<TaskControlMessage>,
-	config: Arc<config::Config>,
+	config: std::sync::RwLock<Arc<config::Config>>,
 	statistics: RwLock<Statistics>,
+	slow_listeners: SlowListeners,
+	// `Statistics::time_played` from before the restart
+	time_played_before: Duration,
+}
+
+pub struct Library {
+	/// the files of all shows one after another
+	pub playlist: Box<[PathBuf]>,
+	// the unscheduled playlist from `dirs` first, then one per schedule slot
+	shows: Box<[Show]>,
+}
+
+struct Show {
+	name: Option<String>,
+	files: Range<usize>,
+}
+
+impl Show {
+	/// the name the rotation knows it by
+	fn key(&self) -> &str {
+		self.name.as_deref().unwrap_or_default()
+	}
+}
+
+impl Library {
+	fn new(
+		mut playlist: Vec<PathBuf>,
+		schedule_files: Vec<Vec<PathBuf>>,
+		config: &config::Config,
+		metadata: &index::Index,
+	) -> Result<Self, Error> {
+		if playlist.is_empty() {
+			return Err(Error::EmptyPlayilist);
+		}
+		sort(&mut playlist, config.order, metadata);
+		let mut shows = vec![Show { name: None, files: 0..playlist.len() }];
+		for (slot, mut files) in config.schedule.iter().zip(schedule_files) {
+			if files.is_empty() {
+				return Err(Error::EmptyShow(slot.name.clone()));
+			}
+			sort(&mut files, config.order, metadata);
+			let files_range = playlist.len()..playlist.len() + files.len();
+			shows.push(Show { name: Some(slot.name.clone()), files: files_range });
+			playlist.extend(files);
+		}
+		Ok(Self { playlist: playlist.into_boxed_slice(), shows: shows.into_boxed_slice() })
+	}
+
+	/// walks all directories again. blocks
+	fn collect(config: &config::Config, metadata: &index::Index) -> Result<Self, Error> {
+		let schedule_files = config.schedule.iter().map(|x| files::collect(&x.dirs)).collect();
+		Self::new(files::collect(&config.dirs), schedule_files, config, metadata)
+	}
+
+	/// the indexed files in the directories, so that startup doesn't wait for the walk.
+	/// none if the index has nothing for a show
+	fn from_index(config: &config::Config, metadata: &index::Index) -> Option<Self> {
+		let files = |dirs: &[config::DirectoryConfig]| {
+			metadata.read(|index| {
+				index.keys().filter(|x| files::matches(dirs, x)).cloned().collect::<Vec<_>>()
+			})
+		};
+		let schedule_files = config.schedule.iter().map(|x| files(&x.dirs)).collect();
+		Self::new(files(&config.dirs), schedule_files, config, metadata).ok()
+	}
+
+	/// `removed` taken out and `added` sorted into the shows whose dirs they're in
+	fn update(
+		&self,
+		added: &[PathBuf],
+		removed: &HashSet<PathBuf>,
+		config: &config::Config,
+		metadata: &index::Index,
+	) -> Result<Self, Error> {
+		let files = |show: &Show, dirs: &[config::DirectoryConfig]| {
+			let old = &self.playlist[show.files.clone()];
+			old.iter()
+				.filter(|x| !removed.contains(*x))
+				.chain(added.iter().filter(|x| files::matches(dirs, x) && !old.contains(x)))
+				.cloned()
+				.collect::<Vec<_>>()
+		};
+		let schedule_files =
+			config.schedule.iter().zip(&self.shows[1..]).map(|(x, show)| files(show, &x.dirs));
+		Self::new(files(&self.shows[0], &config.dirs), schedule_files.collect(), config, metadata)
+	}
+
+	fn show_of(&self, index: usize) -> usize {
+		self.shows.iter().position(|x| x.files.contains(&index)).unwrap_or(0)
+	}
+}
+
+/// puts the files of a show in the order they play in without shuffle
+fn sort(files: &mut [PathBuf], order: config::PlaylistOrder, metadata: &index::Index) {
+	match order {
+		config::PlaylistOrder::Path => files.sort(),
+		config::PlaylistOrder::Natural => files.sort_by(|a, b| files::natural_cmp(a, b)),
+		config::PlaylistOrder::Tags => metadata.read(|index| {
+			files.sort_by_cached_key(|path| {
+				let mediainfo = index.get(path).map(|x| &x.mediainfo);
+				let tag = |f: fn(&cmd::Mediainfo) -> Option<&str>| {
+					mediainfo.and_then(f).map(str::to_lowercase)
+				};
+				(
+					mediainfo.is_some(),
+					tag(|x| x.album_artist.as_deref().or(x.artist.as_deref())),
+					tag(|x| x.album.as_deref()),
+					album::number(mediainfo.and_then(|x| x.disc.as_deref())).unwrap_or(1),
+					album::number(mediainfo.and_then(|x| x.track.as_deref())).unwrap_or(u32::MAX),
+					path.clone(),
+				)
+			});
+		}),
+	}
+}
+
+struct SweeperPool {
+	config: config::SweeperPool,
+	template: Option<config::MixTemplate>,
+	files: Box<[PathBuf]>,
+}
+
+impl SweeperPool {
+	fn from_config(
+		config: &config::Config,
+		// same order as the pools in the config
+		sweeper_files: Vec<Vec<PathBuf>>,
+	) -> Result<Arc<[Self]>, Error> {
+		config
+			.sweepers
+			.iter()
+			.zip(sweeper_files)
+			.map(|(pool, files)| {
+				let template = match &pool.template {
+					Some(name) => Some(
+						config
+							.mix_templates
+							.iter()
+							.find(|x| &x.name == name)
+							.cloned()
+							.ok_or_else(|| Error::UnknownMixTemplate(name.clone()))?,
+					),
+					None => None,
+				};
+				Ok(Self { config: pool.clone(), template, files: files.into_boxed_slice() })
+			})
+			.collect()
+	}
+
+	/// rolls the chance of every pool and picks one of the hits by weight
+	fn pick(pools: &[Self]) -> Option<(&Self, &PathBuf)> {
+		let mut rng = rand::thread_rng();
+		let hits = pools
+			.iter()
+			.filter(|x| !x.files.is_empty() && rng.gen::<f32>() < x.config.chance)
+			.collect::<Vec<_>>();
+		let pool = *hits.choose_weighted(&mut rng, |x| x.config.weight).ok()?;
+		Some((pool, pool.files.choose(&mut rng)?))
+	}
+}
+
+pub struct Output {
+	pub config: config::OutputConfig,
+	tx: broadcast::Sender<audio::Chunk>,
+	// locked while sending so that new listeners get a seamless handoff
+	backlog: std::sync::Mutex<Backlog>,
+	bandwidth: AtomicUsize,
+	// the hls segmenter listens too
+	hls: bool,
+}
+
+#[derive(Default)]
+struct Backlog {
+	// codec headers for listeners joining mid-track
+	stream_header: Bytes,
+	burst: VecDeque<BurstChunk>,
+}
+
+struct BurstChunk {
+	at: tokio::time::Instant,
+	data: Bytes,
+	// the header that was current when this was sent
+	stream_header: Bytes,
+}
+
+impl Backlog {
+	fn push(&mut self, data: Bytes, keep: Duration) {
+		if keep.is_zero() {
+			self.burst.clear();
+			return;
+		}
+		let stream_header = self.stream_header.clone();
+		self.burst.push_back(BurstChunk { at: tokio::time::Instant::now(), data, stream_header });
+		while self.burst.front().is_some_and(|x| x.at.elapsed() > keep) {
+			self.burst.pop_front();
+		}
+	}
+
+	/// what a new listener gets before the live broadcast
+	fn replay(&self) -> VecDeque<Bytes> {
+		let stream_header = self.burst.front().map_or(&self.stream_header, |x| &x.stream_header);
+		(!stream_header.is_empty())
+			.then(|| stream_header.clone())
+			.into_iter()
+			.chain(self.burst.iter().map(|x| x.data.clone()))
+			.collect()
+	}
+}
+
+impl Output {
+	fn new(config: config::OutputConfig, queue: usize) -> Self {
+		Self {
+			config,
+			tx: broadcast::channel(queue).0,
+			backlog: Default::default(),
+			bandwidth: Default::default(),
+			hls: false,
+		}
+	}
+
+	/// receivers that are listeners
+	fn listeners(&self) -> usize {
+		self.tx.receiver_count().saturating_sub(usize::from(self.hls))
+	}
 }
 
 #[derive(Debug, Default)]
@@ -124,101 +421,429 @@ impl AlbumImage {
 	}
 }
 
+/// a stretch of a track that's played by a single reader
+struct Part {
+	start: f32,
+	end: Option<f32>,
+	gain: f32,
+	// next track, crossfade length and the next track's gain
+	crossfade: Option<(PathBuf, f32, f32)>,
+	// file, mix template and where it starts in the song
+	sweeper: Option<(PathBuf, Option<config::MixTemplate>, f32)>,
+	copy_codec: bool,
+}
+
+impl Part {
+	fn span(&self) -> cmd::Span<'_> {
+		cmd::Span {
+			start: self.start,
+			end: self.end,
+			gain: self.gain,
+			crossfade: self.crossfade.as_ref().map(|(next, duration, gain)| cmd::Crossfade {
+				next,
+				duration: *duration,
+				gain: *gain,
+			}),
+		}
+	}
+
+	fn sweeper(&self) -> Option<cmd::Sweeper<'_>> {
+		self.sweeper.as_ref().map(|(path, template, at)| cmd::Sweeper {
+			path,
+			template: template.as_ref(),
+			at: *at,
+		})
+	}
+}
+
 #[derive(Clone, Copy)]
 enum TaskControlMessage {
 	Play,
 	Pause,
+	Live,
+}
+
+/// what the task loop plays, so it can be driven without a real player in tests
+#[async_trait::async_trait]
+trait Task: Send + Sync + 'static {
+	fn control(&self) -> &tokio::sync::watch::Sender<TaskControlMessage>;
+	async fn play_next(&self, player_init_instant: tokio::time::Instant);
+	async fn play_live(&self, player_init_instant: tokio::time::Instant);
+	fn rewind(&self);
+	fn grace_period(&self) -> Duration;
+}
+
+#[async_trait::async_trait]
+impl Task for Player {
+	fn control(&self) -> &tokio::sync::watch::Sender<TaskControlMessage> {
+		&self.inner.task_control_tx
+	}
+
+	async fn play_next(&self, player_init_instant: tokio::time::Instant) {
+		Self::play_next(self, player_init_instant).await;
+	}
+
+	async fn play_live(&self, player_init_instant: tokio::time::Instant) {
+		Self::play_live(self, player_init_instant).await;
+	}
+
+	fn rewind(&self) {
+		Self::rewind(self);
+	}
+
+	fn grace_period(&self) -> Duration {
+		Duration::from_secs(self.config().live.as_ref().map_or(0, |x| x.grace_period.into()))
+	}
 }
 
-pub type PlayerRx = TrackDropStream<tokio_stream::wrappers::BroadcastStream<Bytes>>;
+async fn run_task(task: impl Task) {
+	let mut rx = task.control().subscribe();
+	let player_init_instant = tokio::time::Instant::now();
+	loop {
+		let msg = *rx.borrow_and_update();
+		match msg {
+			TaskControlMessage::Play => loop {
+				tokio::select! {
+					_ = rx.changed() => break,
+					() = task.play_next(player_init_instant) => (),
+				}
+			},
+			TaskControlMessage::Pause => {
+				task.rewind();
+				tokio::select! {
+					_ = rx.changed() => (),
+					() = tokio::time::sleep(Duration::from_secs(999)) => (),
+				}
+			}
+			TaskControlMessage::Live => {
+				task.rewind();
+				let grace_period = task.grace_period();
+				tokio::select! {
+					_ = rx.changed() => (),
+					() = task.play_live(player_init_instant) => {
+						// a reconnecting source sends Live again in the meantime
+						tokio::select! {
+							_ = rx.changed() => (),
+							() = tokio::time::sleep(grace_period) => {
+								let _ = task.control().send(TaskControlMessage::Play);
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+struct LiveSession {
+	// dropped when the source disconnects, which ends the readers
+	tx: broadcast::Sender<Bytes>,
+	mediainfo: cmd::Mediainfo,
+}
+
+/// sends whatever a listener needs to catch up before the live broadcast
+pub struct ListenerStream {
+	backlog: VecDeque<Bytes>,
+	rx: BroadcastStream<audio::Chunk>,
+	inner: Arc<Inner>,
+	// already counted in `SlowListeners::resynced`
+	lagged: bool,
+}
+
+impl futures_core::Stream for ListenerStream {
+	type Item = Result<Bytes, BroadcastStreamRecvError>;
+
+	fn poll_next(
+		mut self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> std::task::Poll<Option<Self::Item>> {
+		if let Some(x) = self.backlog.pop_front() {
+			return std::task::Poll::Ready(Some(Ok(x)));
+		}
+		loop {
+			match std::task::ready!(Pin::new(&mut self.rx).poll_next(cx)) {
+				Some(Ok(x)) => return std::task::Poll::Ready(Some(Ok(x.data))),
+				// the listener fell behind the queue and missed some chunks
+				Some(Err(BroadcastStreamRecvError::Lagged(_))) => {
+					let policy = self.inner.config.read().unwrap().slow_listener;
+					let slow_listeners = &self.inner.slow_listeners;
+					match policy {
+						config::SlowListenerPolicy::Resync => {
+							if !self.lagged {
+								slow_listeners.resynced.fetch_add(1, Ordering::Relaxed);
+								self.lagged = true;
+							}
+						}
+						config::SlowListenerPolicy::Disconnect => {
+							slow_listeners.disconnected.fetch_add(1, Ordering::Relaxed);
+							return std::task::Poll::Ready(None);
+						}
+					}
+					// chunks are whole frames, so the next one is a clean place to continue
+				}
+				None => return std::task::Poll::Ready(None),
+			}
+		}
+	}
+}
+
+// in dB
+const MIN_GAIN: f32 = 0.5;
+// files indexed between saves of the index
+const INDEX_SAVE_INTERVAL: usize = 100;
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+pub type PlayerRx = TrackDropStream<ListenerStream>;
 
 #[derive(Debug)]
 pub enum Error {
 	EmptyPlayilist,
+	NoOutputs,
+	HlsOutput(String),
+	UnknownMixTemplate(String),
+	EmptyShow(String),
+	/// what can't work because ffmpeg is missing
+	NeedsFfmpeg(Vec<String>),
 }
 
 impl Player {
 	pub fn new(
-		playlist: Vec<PathBuf>,
-		sweeper_list: Vec<PathBuf>,
+		// same order as the pools in the config
+		sweeper_files: Vec<Vec<PathBuf>>,
 		config: Arc<config::Config>,
 	) -> Result<Self, Error> {
-		if playlist.is_empty() {
-			return Err(Error::EmptyPlayilist);
+		let metadata = index::Index::load(config.index.clone());
+		// the walk catches up on what changed while it was off
+		let (library, walk) = match Library::from_index(&config, &metadata) {
+			Some(x) => (x, true),
+			None => (Library::collect(&config, &metadata)?, false),
+		};
+		check_ffmpeg(&config, &library)?;
+		if config.outputs.is_empty() {
+			return Err(Error::NoOutputs);
 		}
 
-		let index =
-			if config.shuffle { rand::thread_rng().gen_range(0..playlist.len()) } else { 0 };
-		let tx = tokio::sync::broadcast::channel(4).0;
+		let state = state::State::load(config.state.as_deref());
+		let mut rotation = state.rotation;
+		let show_index = scheduled_show(&config);
+		let show = &library.shows[show_index];
+		// picks up where it left off if that track is still part of the show
+		let resumed = state.playing.and_then(|playing| {
+			let index = show.files.clone().find(|&x| library.playlist[x] == playing.path)?;
+			Some((index, playing.position.max(0.0)))
+		});
+		let resumed_path = resumed.map(|(x, _)| library.playlist[x].clone());
+		let index = if let Some((index, _)) = resumed {
+			index
+		} else if let Some(albums) = albums(&config, show_index) {
+			metadata.read(|index| {
+				album::next(albums, &library.playlist, show.files.clone(), usize::MAX, index)
+			})
+		} else if config.shuffle {
+			let files = &library.playlist[show.files.clone()];
+			show.files.start
+				+ metadata.read(|index| {
+					rotation.pick(
+						show.key(),
+						files,
+						&config,
+						|x| index.get(x).map(|x| &x.mediainfo),
+						|x| weight(&config, show_index, x, index.get(x)),
+					)
+				})
+		} else {
+			show.files.start
+		};
+		let mut outputs = config
+			.outputs
+			.iter()
+			.map(|x| Output::new(x.clone(), config.listener_queue.get()))
+			.collect::<Box<[_]>>();
+		let hls = match &config.hls {
+			Some(hls_config) => {
+				let output = outputs
+					.iter()
+					.position(|x| x.config.mount == hls_config.output)
+					.filter(|&x| outputs[x].config.codec != config::Codec::Opus)
+					.ok_or_else(|| Error::HlsOutput(hls_config.output.clone()))?;
+				Some((output, hls::Hls::new(hls_config, outputs[output].config.codec)))
+			}
+			None => None,
+		};
+		let (hls_output, hls) = hls.unzip();
+		if let Some(x) = hls_output {
+			outputs[x].hls = true;
+		}
+		let sweepers = SweeperPool::from_config(&config, sweeper_files)?;
 		let next_song_tx = tokio::sync::watch::channel(()).0;
+		let mut statistics = state.statistics;
+		// bytes per mount only carry over while the outputs stay the same
+		if statistics.mounts.len() != config.outputs.len() {
+			statistics.mounts = vec![Default::default(); config.outputs.len()].into();
+		}
+		let time_played_before = statistics.time_played;
+		let mut mediainfo = FixedDeque::new(config.mediainfo_history.get());
+		for x in state.history.into_iter().rev() {
+			mediainfo.push(x);
+		}
 
 		let player = Self {
 			inner: Arc::new(Inner {
-				playlist: playlist.into_boxed_slice(),
-				sweeper_list: sweeper_list.into_boxed_slice(),
+				library: Arc::new(library).into(),
+				shuffle: config.shuffle.into(),
+				sweepers: sweepers.into(),
 				album_art: Default::default(),
 				index: index.into(),
-				mediainfo: FixedDeque::new(config.mediainfo_history.get()).into(),
-				tx,
+				cursor: index.into(),
+				faded_into: resumed.into(),
+				resumed: resumed_path.into(),
+				playing: Default::default(),
+				live: Default::default(),
+				rotation: rotation.into(),
+				requests: config
+					.requests
+					.clone()
+					.map(|x| std::sync::Mutex::new(requests::Requests::new(x))),
+				queue_tx: tokio::sync::watch::channel(()).0,
+				loudness_failed: Default::default(),
+				metadata,
+				scan: Default::default(),
+				mediainfo: mediainfo.into(),
+				outputs,
+				hls,
 				next_song_tx,
 				task_control_tx: tokio::sync::watch::channel(TaskControlMessage::Play).0,
-				config,
-				statistics: Default::default(),
+				statistics: statistics.into(),
+				slow_listeners: state.slow_listeners,
+				time_played_before,
+				config: config.into(),
 			}),
 		};
 
 		player.clone().spawn_task();
+		if walk {
+			let player = player.clone();
+			tokio::spawn(async move {
+				if let Err(e) = player.rescan().await {
+					println!("Could not walk the directories: {e:?}");
+				}
+			});
+		} else {
+			player.scan();
+		}
+		player.clone().spawn_schedule_task();
+		player.clone().spawn_state_task();
+		if let Some(output) = hls_output {
+			let rx = player.inner.outputs[output].tx.subscribe();
+			let player = player.clone();
+			tokio::spawn(async move { player.inner.hls.as_ref().unwrap().run(rx).await });
+		}
 
 		Ok(player)
 	}
 
 	fn spawn_task(self) {
-		tokio::spawn({
-			async move {
-				let mut rx = self.inner.task_control_tx.subscribe();
-				let player_init_instant = tokio::time::Instant::now();
-				loop {
-					let msg = *rx.borrow();
-					match msg {
-						TaskControlMessage::Play => loop {
-							tokio::select! {
-								_ = rx.changed() => break,
-								_ = self.play_next(player_init_instant) => (),
-							}
-						},
-						TaskControlMessage::Pause => {
-							tokio::select! {
-								_ = rx.changed() => break,
-								_ = tokio::time::sleep(Duration::from_secs(999)) => (),
-							}
-						}
-					}
+		tokio::spawn(run_task(self));
+	}
+
+	/// saves the position in the current track, the statistics and the index every now and then
+	fn spawn_state_task(self) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(STATE_SAVE_INTERVAL);
+			interval.tick().await;
+			loop {
+				interval.tick().await;
+				if !matches!(*self.inner.task_control_tx.borrow(), TaskControlMessage::Pause) {
+					self.save_state().await;
 				}
+				// what the tracks played since then looked up
+				self.save_index().await;
+			}
+		});
+	}
+
+	/// the interrupted track starts over once the playlist continues
+	fn rewind(&self) {
+		if let Some((_, start, since)) = self.inner.playing.lock().unwrap().as_mut() {
+			*start = 0.0;
+			*since = None;
+		}
+	}
+
+	/// cuts into the current song when a new show starts
+	fn spawn_schedule_task(self) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(1));
+			// only a slot boundary cuts, requests and jumps into other shows play out
+			let mut previous = scheduled_show(&self.config());
+			loop {
+				interval.tick().await;
+				// a boundary crossed while paused or live cuts once the playlist continues
+				if !matches!(*self.inner.task_control_tx.borrow(), TaskControlMessage::Play) {
+					continue;
+				}
+				let index = self.index();
+				let library = self.library();
+				let config = self.config();
+				let show = scheduled_show(&config);
+				let crossed = std::mem::replace(&mut previous, show) != show;
+				// the song was removed in a rescan, the next one is picked from the right show anyway
+				if config.schedule_switch != config::ScheduleSwitch::Cut
+					|| !crossed || index >= library.playlist.len()
+					|| library.show_of(index) == show
+				{
+					continue;
+				}
+				self.inner.index.store(self.pick_next(), Ordering::Relaxed);
+				// restarts play_next with the new index
+				let _ = self.inner.task_control_tx.send(TaskControlMessage::Play);
 			}
 		});
 	}
 
 	// #[allow(clippy::significant_drop_tightening, clippy::significant_drop_in_scrutinee)]
 	async fn play_next(&self, player_init_instant: tokio::time::Instant) {
-		let Inner { playlist, sweeper_list, album_art, index, tx, config, .. } = &*self.inner;
+		let Inner { album_art, index, outputs, .. } = &*self.inner;
+		let config = self.config();
+		let library = self.library();
+		let playlist = &library.playlist;
 		let index = index.load(Ordering::Relaxed);
 
-		let input = &playlist[index];
+		let Some(input) = playlist.get(index) else {
+			// the library shrank in a rescan
+			self.next();
+			return;
+		};
+		let start = self
+			.inner
+			.faded_into
+			.lock()
+			.unwrap()
+			.take()
+			.filter(|x| x.0 == index)
+			.map_or(0.0, |x| x.1);
 
-		let mediainfo = match cmd::mediainfo(input).await {
+		let entry = match self.lookup(input).await {
 			Ok(x) => x,
 			Err(x) => {
 				println!("{:?}\tbroken file - skipping: {x}", playlist[index].file_name().unwrap());
+				self.advance(index, input);
 				tokio::time::sleep(Duration::from_secs(1)).await;
 				self.next();
 				return;
 			}
 		};
 
-		let album_image_path = match try_album_arts(input).await {
-			Some(data) => {
-				album_art.write().await.set(data.1);
-				Some(data.0)
+		// the passthrough backend can run without ffmpeg
+		let has_ffmpeg = cmd::has_ffmpeg();
+
+		let mut mediainfo = entry.mediainfo;
+		let album_image =
+			if has_ffmpeg { find_album_art(input, entry.cover.as_deref()).await } else { None };
+		let album_image_path = match album_image {
+			Some((path, data)) => {
+				album_art.write().await.set(data);
+				Some(path)
 			}
 			None => {
 				album_art.write().await.clear();
@@ -226,98 +851,776 @@ impl Player {
 			}
 		};
 
-		let sweeper_path = {
-			let mut rng = rand::thread_rng();
-			(rng.gen::<f32>() <= config.sweeper_chance).then(|| {
-				// checked non empty in main
-				sweeper_list.iter().choose(&mut rng).unwrap()
+		let show_index = library.show_of(index);
+		let show = &library.shows[show_index];
+		// in album mode, where the track is in its album and how many tracks the album has
+		let album = albums(&config, show_index).map(|albums| {
+			let tracks = self
+				.inner
+				.metadata
+				.read(|x| album::tracks(albums, playlist, show.files.clone(), index, x));
+			(albums, tracks.iter().position(|&x| x == index).unwrap_or(0), tracks.len())
+		});
+
+		let sweepers = self.inner.sweepers.read().unwrap().clone();
+		let between_albums =
+			album.is_none_or(|(albums, position, _)| !albums.sweepers_between || position == 0);
+		let sweeper =
+			if has_ffmpeg && between_albums { SweeperPool::pick(&sweepers) } else { None };
+		// where the sweeper starts in the song. outros need to know how long the song is
+		let sweeper_at = sweeper.and_then(|(pool, _)| match &pool.template {
+			Some(x) if x.position == config::SweeperPosition::Outro => {
+				mediainfo.duration.map(|duration| duration as f32 - start - x.offset)
+			}
+			Some(x) => Some(x.offset),
+			None => Some(0.0),
+		});
+		let sweeper = sweeper.filter(|_| sweeper_at.is_some());
+		let gain = self.gain(mediainfo.loudness.or(entry.loudness));
+		let copy_codec = outputs
+			.iter()
+			.map(|x| {
+				!config.transcode_all
+					&& gain == 0.0 && x.config.bitrate.is_none()
+					&& x.config.codec.can_copy(&mediainfo.codec)
 			})
+			.collect::<Vec<_>>();
+
+		if !has_ffmpeg && copy_codec.contains(&false) {
+			println!(
+				"{:?}\tneeds ffmpeg for transcoding - skipping",
+				playlist[index].file_name().unwrap()
+			);
+			self.advance(index, input);
+			tokio::time::sleep(Duration::from_secs(1)).await;
+			self.next();
+			return;
+		}
+
+		// the track goes on air, so a request for it is done. before the next one is picked
+		self.advance(index, input);
+
+		// the overlap has to be mixed, so it needs ffmpeg
+		let crossfade = mediainfo
+			.duration
+			.map(|x| x as f32)
+			.filter(|x| has_ffmpeg && config.crossfade > 0.0 && x - start > config.crossfade * 2.0)
+			// tracks of an album flow into each other as they are
+			.filter(|_| album.is_none_or(|(_, position, len)| position + 1 >= len))
+			.map(|duration| (self.pick_next(), duration - config.crossfade));
+		let next_gain = if let Some((next, _)) = crossfade {
+			let next = &playlist[next];
+			let loudness =
+				self.lookup(next).await.ok().and_then(|x| x.mediainfo.loudness.or(x.loudness));
+			self.gain(loudness)
+		} else {
+			0.0
 		};
-		let copy_codec = !config.transcode_all && mediainfo.codec == "mp3";
 
 		println!(
-			"{:?}\t(codec: {}, copy: {}, sweeper: {}, album image: {})",
+			"{:?}\t(codec: {}, copy: {}, gain: {:+.1} dB, sweeper: {}, album image: {})",
 			playlist[index].file_name().unwrap(),
 			mediainfo.codec,
-			if copy_codec { "yes" } else { "no" },
-			sweeper_path.as_ref().map(|x| x.file_name().unwrap().to_str().unwrap()).unwrap_or("no"),
+			if copy_codec.iter().any(|x| *x) { "yes" } else { "no" },
+			gain,
+			sweeper.map_or_else(
+				|| "no".to_string(),
+				|(pool, x)| format!(
+					"{}/{}",
+					pool.config.name,
+					x.file_name().unwrap().to_string_lossy()
+				)
+			),
 			album_image_path
 				.as_ref()
 				.map(|x| x.file_name().unwrap().to_str().unwrap())
 				.unwrap_or("none"),
 		);
 
-		self.inner.mediainfo.write().await.push(mediainfo);
+		mediainfo.played_at = Some(chrono::Utc::now());
+		mediainfo.show = show.name.clone();
+		if let Some(requests) = &self.inner.requests {
+			requests.lock().unwrap().played(input);
+		}
+		let resumed = self.inner.resumed.lock().unwrap().take().is_some_and(|x| x == *input);
+		if !resumed {
+			let weight = self
+				.inner
+				.metadata
+				.read(|x| weight(&config, show_index, input, x.get(input.as_path())));
+			let mut rotation = self.inner.rotation.lock().unwrap();
+			rotation.played(show.key(), input, &mediainfo, weight, &config);
+		}
+		*self.inner.playing.lock().unwrap() =
+			Some((input.clone(), start, Some(tokio::time::Instant::now())));
+		mediainfo.sweeper = sweeper.map(|(pool, x)| cmd::SweeperInfo {
+			pool: pool.config.name.clone(),
+			kind: pool.config.kind,
+			filename: x.file_name().unwrap_or_default().into(),
+		});
+		let mut history = self.inner.mediainfo.write().await;
+		// the saved history ends with the resumed track already
+		if resumed && history.as_slice().first().is_some_and(|x| x.filename == mediainfo.filename) {
+			history.pop();
+		}
+		history.push(mediainfo);
+		drop(history);
 
 		// notify about next song after everything is updated
 		let _ = self.inner.next_song_tx.send(());
 
-		#[allow(clippy::significant_drop_tightening)]
-		let transmit_reader = |mut reader: FFMpegAudioReader| async move {
-			let buf = &mut [0u8; 4096];
-			let mut bandwidth_instant = tokio::time::Instant::now();
-			let mut bandwidth_acc = 0;
-			loop {
-				let data = reader.read_data(buf).await.unwrap();
-				match data {
-					audio::Data::Audio(0) => break,
-					audio::Data::Audio(read) => {
-						let _ = tx.send(Bytes::copy_from_slice(&buf[..read]));
-						bandwidth_acc += read;
-
-						// clippy::significant_drop_tightening
-						let mut stats = self.inner.statistics.write().await;
-						if copy_codec {
-							stats.bytes_copied += read;
-						} else {
-							stats.bytes_transcoded += read;
-						}
-						stats.bytes_sent += read * tx.receiver_count();
+		let mut transmitters = JoinSet::new();
+		for (output_index, copy_codec) in copy_codec.into_iter().enumerate() {
+			let part = Part {
+				start,
+				end: None,
+				gain,
+				crossfade: crossfade
+					.map(|(next, _)| (playlist[next].clone(), config.crossfade, next_gain)),
+				sweeper: sweeper
+					.zip(sweeper_at)
+					.map(|((pool, path), at)| (path.clone(), pool.template.clone(), at)),
+				copy_codec,
+			};
+			let parts = match crossfade {
+				// copy as much as possible, only the overlap gets transcoded
+				Some((_, fade_at)) if copy_codec && part.sweeper.is_none() => vec![
+					Part { end: Some(fade_at), crossfade: None, sweeper: None, ..part },
+					Part { start: fade_at, copy_codec: false, ..part },
+				],
+				_ => vec![part],
+			};
+			transmitters.spawn(self.clone().transmit(
+				output_index,
+				input.clone(),
+				parts,
+				player_init_instant,
+			));
+		}
+		while transmitters.join_next().await.is_some() {}
+
+		match crossfade {
+			// a rescan while the song was playing invalidates the index
+			Some((next, _)) if Arc::ptr_eq(&library, &self.library()) => {
+				*self.inner.faded_into.lock().unwrap() = Some((next, config.crossfade));
+				self.inner.index.store(next, Ordering::Relaxed);
+			}
+			_ => self.next(),
+		}
+	}
 
-						if bandwidth_instant.elapsed() >= Duration::from_secs(1) {
-							stats.target_badwidth = bandwidth_acc * tx.receiver_count();
-							bandwidth_acc = 0;
-							bandwidth_instant = tokio::time::Instant::now();
+	/// sends the live source to all outputs until it disconnects
+	async fn play_live(&self, player_init_instant: tokio::time::Instant) {
+		let Inner { outputs, .. } = &*self.inner;
+		let config = self.config();
+		let Some((mut mediainfo, receivers)) = self.inner.live.lock().unwrap().as_ref().map(|x| {
+			(x.mediainfo.clone(), outputs.iter().map(|_| x.tx.subscribe()).collect::<Vec<_>>())
+		}) else {
+			return;
+		};
+
+		self.inner.album_art.write().await.clear();
+		mediainfo.played_at = Some(chrono::Utc::now());
+		self.inner.mediainfo.write().await.push(mediainfo.clone());
+		let _ = self.inner.next_song_tx.send(());
+
+		let burst_duration = Duration::from_secs(config.burst_duration.into());
+		let mut transmitters = JoinSet::new();
+		for (output_index, (output, rx)) in outputs.iter().zip(receivers).enumerate() {
+			let reader = live::LiveAudioReader::start(
+				rx,
+				output.config.codec,
+				output.config.bitrate.unwrap_or(config.bitrate),
+				mediainfo.clone(),
+			);
+			let this = self.clone();
+			transmitters.spawn(async move {
+				this.transmit_part(
+					output_index,
+					Box::new(reader),
+					false,
+					burst_duration,
+					player_init_instant,
+				)
+				.await;
+			});
+		}
+		while transmitters.join_next().await.is_some() {}
+	}
+
+	/// returns `None` if another source is live already
+	pub fn start_live(&self, mediainfo: cmd::Mediainfo) -> Option<broadcast::Sender<Bytes>> {
+		let tx = {
+			let mut live = self.inner.live.lock().unwrap();
+			if live.is_some() {
+				return None;
+			}
+			let tx = broadcast::channel(64).0;
+			*live = Some(LiveSession { tx: tx.clone(), mediainfo });
+			tx
+		};
+		let _ = self.inner.task_control_tx.send(TaskControlMessage::Live);
+		Some(tx)
+	}
+
+	pub fn end_live(&self) {
+		self.inner.live.lock().unwrap().take();
+	}
+
+	/// what the dj sends as the current song
+	pub async fn set_live_title(&self, title: String) {
+		let mediainfo = self.inner.live.lock().unwrap().as_mut().map(|session| {
+			session.mediainfo.title = Some(title);
+			session.mediainfo.clone()
+		});
+		let Some(mut mediainfo) = mediainfo else {
+			return;
+		};
+		mediainfo.played_at = Some(chrono::Utc::now());
+		self.inner.mediainfo.write().await.push(mediainfo);
+		let _ = self.inner.next_song_tx.send(());
+	}
+
+	async fn probe(&self, input: &Path) -> Result<cmd::Mediainfo, String> {
+		#[cfg(feature = "passthrough")]
+		if self.config().backend == config::Backend::Passthrough {
+			let input = input.to_path_buf();
+			return tokio::task::spawn_blocking(move || passthrough::mediainfo(&input))
+				.await
+				.map_err(|x| x.to_string())?;
+		}
+		cmd::mediainfo(input).await
+	}
+
+	/// the indexed metadata, probing the file only if it changed
+	async fn lookup(&self, input: &Path) -> Result<index::Entry, String> {
+		let player = self.clone();
+		let path = input.to_path_buf();
+		let entry = tokio::task::spawn_blocking(move || player.inner.metadata.get(&path));
+		if let Some(x) = entry.await.unwrap() {
+			return Ok(x);
+		}
+		// saved with the state
+		self.index_file(input, &mut HashMap::new()).await
+	}
+
+	/// `covers` has the cover of every directory seen so far, so that a batch looks once per album
+	async fn index_file(
+		&self,
+		input: &Path,
+		covers: &mut HashMap<PathBuf, Option<PathBuf>>,
+	) -> Result<index::Entry, String> {
+		let mediainfo = self.probe(input).await?;
+		let path = input.to_path_buf();
+		let dir = input.parent().map(Path::to_path_buf);
+		let cached = dir.as_ref().and_then(|x| covers.get(x).cloned());
+		let (entry, cover) = tokio::task::spawn_blocking(move || {
+			let cover = cached.unwrap_or_else(|| path.parent().and_then(directory_cover));
+			index::Entry::new(&path, mediainfo, cover.clone()).map(|x| (x, cover))
+		})
+		.await
+		.unwrap()
+		.map_err(|x| x.to_string())?;
+		if let Some(x) = dir {
+			covers.insert(x, cover);
+		}
+		self.inner.metadata.insert(input.to_path_buf(), entry.clone());
+		Ok(entry)
+	}
+
+	async fn save_state(&self) {
+		let Some(path) = self.config().state.clone() else {
+			return;
+		};
+		let playing = self.inner.playing.lock().unwrap().as_ref().map(|(path, start, since)| {
+			state::Playing {
+				path: path.clone(),
+				position: start + since.map_or(0.0, |x| x.elapsed().as_secs_f32()),
+			}
+		});
+		let rotation = self.inner.rotation.lock().unwrap().clone();
+		let statistics = self.inner.statistics.read().await.clone();
+		let slow_listeners = self.inner.slow_listeners.clone();
+		let history = self.inner.mediainfo.read().await.as_slice().to_vec();
+		let state = state::State { rotation, playing, statistics, slow_listeners, history };
+		if let Err(e) = tokio::task::spawn_blocking(move || state.save(&path)).await.unwrap() {
+			println!("Could not save the state: {e}");
+		}
+	}
+
+	async fn save_index(&self) {
+		let player = self.clone();
+		if let Err(e) =
+			tokio::task::spawn_blocking(move || player.inner.metadata.save()).await.unwrap()
+		{
+			println!("Could not save the index: {e}");
+		}
+	}
+
+	/// saves everything that outlives a restart
+	pub async fn shutdown(&self) {
+		self.save_state().await;
+		self.save_index().await;
+	}
+
+	/// indexes everything in the library that isn't yet in the background
+	fn scan(&self) {
+		let player = self.clone();
+		let handle = tokio::spawn(async move {
+			let library = player.library();
+			let config = player.config();
+			// an index that isn't kept would be built all over on every start. files are probed
+			// as they play instead, unless the playlist goes by their tags
+			if config.index.is_none() && config.order != config::PlaylistOrder::Tags {
+				player.analyze_files(&library.playlist).await;
+				return;
+			}
+			// stats every file, which is slow on network mounts
+			let missing = {
+				let player = player.clone();
+				let library = library.clone();
+				tokio::task::spawn_blocking(move || {
+					let metadata = &player.inner.metadata;
+					let mut files =
+						library.playlist.iter().map(PathBuf::as_path).collect::<HashSet<_>>();
+					metadata.retain(|x| files.contains(x));
+					// in playlist order, files can be in more than one show
+					library
+						.playlist
+						.iter()
+						.filter(|x| files.remove(x.as_path()) && metadata.get(x).is_none())
+						.cloned()
+						.collect::<Vec<_>>()
+				})
+				.await
+				.unwrap()
+			};
+			player.index_files(&missing).await;
+			player.analyze_files(&library.playlist).await;
+		});
+		let previous = self.inner.scan.lock().unwrap().replace(handle.abort_handle());
+		if let Some(x) = previous {
+			x.abort();
+		}
+	}
+
+	async fn index_files(&self, files: &[PathBuf]) {
+		if !files.is_empty() {
+			println!("Indexing {} files", files.len());
+		}
+		let mut covers = HashMap::new();
+		for (i, x) in files.iter().enumerate() {
+			if let Err(e) = self.index_file(x, &mut covers).await {
+				println!("{:?}\tcould not index: {e}", x.file_name().unwrap_or_default());
+			}
+			if (i + 1) % INDEX_SAVE_INTERVAL == 0 {
+				self.save_index().await;
+			}
+		}
+		self.save_index().await;
+
+		// the new tags put the files in their place
+		let config = self.config();
+		if !files.is_empty() && config.order == config::PlaylistOrder::Tags {
+			let update = self.library().update(&[], &HashSet::new(), &config, &self.inner.metadata);
+			if let Ok(library) = update {
+				self.set_library(library);
+			}
+		}
+	}
+
+	/// dB that bring a track to the target loudness. tiny corrections aren't worth a transcode
+	/// tracks that weren't analyzed yet play as they are
+	fn gain(&self, loudness: Option<f32>) -> f32 {
+		let Some(target) = self.config().target_lufs else {
+			return 0.0;
+		};
+		if !cmd::has_ffmpeg() {
+			return 0.0;
+		}
+		loudness.map(|x| target - x).filter(|x| x.abs() >= MIN_GAIN).unwrap_or(0.0)
+	}
+
+	/// measures the indexed files that have no replaygain tags. decodes each of them, so it's slow
+	async fn analyze_files(&self, files: &[PathBuf]) {
+		if self.config().target_lufs.is_none() || !cmd::has_ffmpeg() {
+			return;
+		}
+		let missing = self.inner.metadata.read(|index| {
+			let failed = self.inner.loudness_failed.lock().unwrap();
+			let mut seen = HashSet::new();
+			files
+				.iter()
+				.filter(|x| {
+					index
+						.get(*x)
+						.is_some_and(|x| x.mediainfo.loudness.is_none() && x.loudness.is_none())
+						&& !failed.contains(*x)
+						&& seen.insert(*x)
+				})
+				.cloned()
+				.collect::<Vec<_>>()
+		});
+		if !missing.is_empty() {
+			println!("Analyzing the loudness of {} files", missing.len());
+		}
+		for (i, x) in missing.iter().enumerate() {
+			match cmd::loudness(x).await {
+				Ok(loudness) => self.inner.metadata.set_loudness(x, loudness),
+				Err(e) => {
+					println!("{:?}\t{e}", x.file_name().unwrap_or_default());
+					self.inner.loudness_failed.lock().unwrap().insert(x.clone());
+				}
+			}
+			if (i + 1) % INDEX_SAVE_INTERVAL == 0 {
+				self.save_index().await;
+			}
+		}
+		if !missing.is_empty() {
+			self.save_index().await;
+		}
+	}
+
+	fn start_reader(
+		&self,
+		input: &Path,
+		part: &Part,
+		output: &config::OutputConfig,
+	) -> Box<dyn AudioReader> {
+		let config = self.config();
+		#[cfg(feature = "passthrough")]
+		if config.backend == config::Backend::Passthrough
+			&& part.copy_codec
+			&& part.sweeper.is_none()
+			&& part.crossfade.is_none()
+		{
+			return Box::new(passthrough::PassthroughReader::start(input, part.start, part.end));
+		}
+		Box::new(FFMpegAudioReader::start(
+			input,
+			part.span(),
+			part.sweeper(),
+			output.codec,
+			output.bitrate.unwrap_or(config.bitrate),
+			part.copy_codec,
+		))
+	}
+
+	#[allow(clippy::significant_drop_tightening)]
+	async fn transmit(
+		self,
+		output_index: usize,
+		input: PathBuf,
+		parts: Vec<Part>,
+		player_init_instant: tokio::time::Instant,
+	) {
+		let output = &self.inner.outputs[output_index];
+		let burst_duration = Duration::from_secs(self.config().burst_duration.into());
+
+		// readers are started one after another so that ffmpeg's -re pacing stays intact
+		for part in parts {
+			let reader = self.start_reader(&input, &part, &output.config);
+			self.transmit_part(
+				output_index,
+				reader,
+				part.copy_codec,
+				burst_duration,
+				player_init_instant,
+			)
+			.await;
+		}
+	}
+
+	#[allow(clippy::significant_drop_tightening)]
+	async fn transmit_part(
+		&self,
+		output_index: usize,
+		mut reader: Box<dyn AudioReader>,
+		copy_codec: bool,
+		burst_duration: Duration,
+		player_init_instant: tokio::time::Instant,
+	) {
+		let Inner { outputs, statistics, .. } = &*self.inner;
+		let output = &outputs[output_index];
+		let tx = &output.tx;
+
+		let mut framer = audio::Framer::new(output.config.codec);
+		let mut ogg_header =
+			(output.config.codec == config::Codec::Opus).then(audio::OggHeaderCollector::default);
+		if ogg_header.is_some() {
+			// the new track brings its own headers
+			output.backlog.lock().unwrap().stream_header = Bytes::new();
+		}
+		let buf = &mut [0u8; 4096];
+		let mut bandwidth_instant = tokio::time::Instant::now();
+		let mut bandwidth_acc = 0;
+		loop {
+			let data = reader.read_data(buf).await.unwrap();
+			match data {
+				audio::Data::Audio(0) => break,
+				audio::Data::Audio(read) => {
+					let chunk = framer.as_mut().map_or_else(
+						|| audio::Chunk::unframed(Bytes::copy_from_slice(&buf[..read])),
+						|x| x.push(&buf[..read]),
+					);
+					if chunk.data.is_empty() {
+						continue;
+					}
+					let read = chunk.data.len();
+					{
+						let mut backlog = output.backlog.lock().unwrap();
+						backlog.push(chunk.data.clone(), burst_duration);
+						let _ = tx.send(chunk);
+						// the chunk with the end of the headers still goes out with the old ones
+						if let Some(header) = ogg_header.as_mut().and_then(|x| x.push(&buf[..read]))
+						{
+							backlog.stream_header = header.into();
+							ogg_header = None;
 						}
 					}
-					audio::Data::Error(err) => {
-						println!("ffmpeg error: {:?}", err);
-						break;
+					bandwidth_acc += read;
+
+					// clippy::significant_drop_tightening
+					let mut stats = statistics.write().await;
+					if copy_codec {
+						stats.bytes_copied += read;
+					} else {
+						stats.bytes_transcoded += read;
+					}
+					let listeners = output.listeners();
+					stats.bytes_sent += read * listeners;
+					stats.mounts[output_index].bytes_sent += read * listeners;
+
+					if bandwidth_instant.elapsed() >= Duration::from_secs(1) {
+						output.bandwidth.store(bandwidth_acc * listeners, Ordering::Relaxed);
+						stats.target_badwidth =
+							outputs.iter().map(|x| x.bandwidth.load(Ordering::Relaxed)).sum();
+						bandwidth_acc = 0;
+						bandwidth_instant = tokio::time::Instant::now();
 					}
 				}
-				self.inner.statistics.write().await.time_played = player_init_instant.elapsed();
+				audio::Data::Error(err) => {
+					println!("ffmpeg error: {:?}", err);
+					break;
+				}
 			}
-		};
+			statistics.write().await.time_played =
+				self.inner.time_played_before + player_init_instant.elapsed();
+		}
+	}
 
-		transmit_reader(audio::FFMpegAudioReader::start(
-			input,
-			sweeper_path,
-			config.bitrate,
-			copy_codec,
-		))
-		.await;
+	pub fn set_index(&self, index: usize) {
+		self.inner.index.store(index, Ordering::Relaxed);
+	}
+
+	/// interrupts the current song. dropping play_next kills its readers
+	fn restart(&self) {
+		let task_control_tx = &self.inner.task_control_tx;
+		if matches!(*task_control_tx.borrow(), TaskControlMessage::Play) {
+			let _ = task_control_tx.send(TaskControlMessage::Play);
+		}
+	}
+
+	pub fn skip(&self) {
+		self.inner.faded_into.lock().unwrap().take();
 		self.next();
+		self.restart();
 	}
 
-	pub fn set_index(&mut self, index: usize) {
-		self.inner.index.store(index, Ordering::Relaxed);
+	/// returns false if there's no such library entry
+	pub fn jump(&self, index: usize) -> bool {
+		if index >= self.library().playlist.len() {
+			return false;
+		}
+		self.inner.faded_into.lock().unwrap().take();
+		self.set_index(index);
+		self.restart();
+		true
+	}
+
+	pub fn pause(&self) {
+		let _ = self.inner.task_control_tx.send(TaskControlMessage::Pause);
+	}
+
+	pub fn resume(&self) {
+		let task_control_tx = &self.inner.task_control_tx;
+		if matches!(*task_control_tx.borrow(), TaskControlMessage::Pause) {
+			let live = self.inner.live.lock().unwrap().is_some();
+			let _ = task_control_tx.send(if live {
+				TaskControlMessage::Live
+			} else {
+				TaskControlMessage::Play
+			});
+		}
+	}
+
+	pub fn set_shuffle(&self, shuffle: bool) {
+		self.inner.shuffle.store(shuffle, Ordering::Relaxed);
+	}
+
+	/// walks the directories again. the current song keeps playing, returns the new library size
+	pub async fn rescan(&self) -> Result<usize, Error> {
+		let config = self.config();
+		let player = self.clone();
+		let library =
+			tokio::task::spawn_blocking(move || Library::collect(&config, &player.inner.metadata))
+				.await
+				.unwrap()?;
+		let len = self.set_library(library);
+		self.scan();
+		Ok(len)
+	}
+
+	/// applies a changed config. returns the settings that only take effect after a restart
+	pub async fn reload(
+		&self,
+		config: config::Config,
+		// same order as the pools in the config
+		sweeper_files: Vec<Vec<PathBuf>>,
+	) -> Result<Vec<&'static str>, Error> {
+		let old = self.config();
+		let sweepers = SweeperPool::from_config(&config, sweeper_files)?;
+		let config = Arc::new(config);
+		// collected before anything is applied so that a bad config changes nothing
+		let library = if config.dirs != old.dirs
+			|| config.schedule != old.schedule
+			|| config.order != old.order
+		{
+			let config = config.clone();
+			let player = self.clone();
+			let library = tokio::task::spawn_blocking(move || {
+				Library::collect(&config, &player.inner.metadata)
+			});
+			Some(library.await.unwrap()?)
+		} else {
+			None
+		};
+		check_ffmpeg(&config, library.as_ref().unwrap_or(&self.library()))?;
+
+		let roots = |x: &config::Config| {
+			x.dirs
+				.iter()
+				.chain(x.schedule.iter().flat_map(|x| x.dirs.iter()))
+				.map(|x| x.root.clone())
+				.collect::<HashSet<_>>()
+		};
+		let restart = [
+			("host", config.host != old.host),
+			("port", config.port != old.port),
+			("enable_webui", config.enable_webui != old.enable_webui),
+			("enable_mediainfo", config.enable_mediainfo != old.enable_mediainfo),
+			("outputs", config.outputs != old.outputs),
+			("hls", config.hls != old.hls),
+			("listener_queue", config.listener_queue != old.listener_queue),
+			("live", config.live != old.live),
+			("requests", config.requests.is_some() != old.requests.is_some()),
+			("admin", config.admin.is_some() != old.admin.is_some()),
+			("index", config.index != old.index),
+			// the watcher only knows the roots from the start
+			("watching new dirs", !roots(&config).is_subset(&roots(&old))),
+		]
+		.into_iter()
+		.filter_map(|(name, changed)| changed.then_some(name))
+		.collect();
+
+		if config.shuffle != old.shuffle {
+			self.set_shuffle(config.shuffle);
+		}
+		if config.mediainfo_history != old.mediainfo_history {
+			self.inner.mediainfo.write().await.resize(config.mediainfo_history.get());
+		}
+		if let (Some(requests), Some(x)) = (&self.inner.requests, &config.requests) {
+			requests.lock().unwrap().set_config(x.clone());
+		}
+		*self.inner.sweepers.write().unwrap() = sweepers;
+		let analyze = config.target_lufs.is_some() && old.target_lufs.is_none();
+		*self.inner.config.write().unwrap() = config;
+		if let Some(library) = library {
+			self.set_library(library);
+			self.scan();
+		} else if analyze {
+			// the files that need it get analyzed in the background
+			self.scan();
+		}
+		Ok(restart)
+	}
+
+	/// applies changes to the directories without walking them again
+	pub fn update_library(
+		&self,
+		added: &[PathBuf],
+		removed: &HashSet<PathBuf>,
+	) -> Result<usize, Error> {
+		let library =
+			self.library().update(added, removed, &self.config(), &self.inner.metadata)?;
+		let len = self.set_library(library);
+		self.inner.metadata.retain(|x| !removed.contains(x));
+		let player = self.clone();
+		let added = added.to_vec();
+		tokio::spawn(async move {
+			player.index_files(&added).await;
+			player.analyze_files(&added).await;
+		});
+		Ok(len)
+	}
+
+	/// swaps the library, moving everything that points into it over. returns the new size
+	fn set_library(&self, library: Library) -> usize {
+		let len = library.playlist.len();
+		let old = self.library();
+		let find = |path: &Path| library.playlist.iter().position(|x| x == path);
+		if let Some(requests) = &self.inner.requests {
+			requests.lock().unwrap().remap(find);
+			let _ = self.inner.queue_tx.send(());
+		}
+		let index = old.playlist.get(self.index()).and_then(|x| find(x));
+		let cursor =
+			old.playlist.get(self.inner.cursor.load(Ordering::Relaxed)).and_then(|x| find(x));
+		{
+			let mut faded_into = self.inner.faded_into.lock().unwrap();
+			*faded_into = faded_into.take().and_then(|(x, start)| {
+				old.playlist.get(x).and_then(|x| find(x)).map(|x| (x, start))
+			});
+		}
+
+		*self.inner.library.write().unwrap() = Arc::new(library);
+		match index {
+			Some(x) => self.set_index(x),
+			// the song is gone. it keeps playing, whatever comes next is from the new library
+			None => self.set_index(usize::MAX),
+		}
+		self.inner.cursor.store(cursor.unwrap_or(usize::MAX), Ordering::Relaxed);
+		len
 	}
 
 	pub fn index(&self) -> usize {
 		self.inner.index.load(Ordering::Relaxed)
 	}
 
-	pub fn current(&self) -> &Path {
-		&self.inner.playlist[self.inner.index.load(Ordering::Relaxed)]
+	pub fn library(&self) -> Arc<Library> {
+		self.inner.library.read().unwrap().clone()
+	}
+
+	pub fn outputs(&self) -> &[Output] {
+		&self.inner.outputs
 	}
 
-	pub fn files(&self) -> &[PathBuf] {
-		&self.inner.playlist
+	pub fn hls(&self) -> Option<&hls::Hls> {
+		self.inner.hls.as_ref()
 	}
 
-	pub fn subscribe(&self) -> PlayerRx {
-		let _tx = self.inner.tx.subscribe();
-		let stream = tokio_stream::wrappers::BroadcastStream::new(self.inner.tx.subscribe());
+	pub fn subscribe(&self, output_index: usize) -> PlayerRx {
+		let output = &self.inner.outputs[output_index];
+		let (rx, backlog) = {
+			let backlog = output.backlog.lock().unwrap();
+			(output.tx.subscribe(), backlog.replay())
+		};
+		let stream = ListenerStream {
+			backlog,
+			rx: BroadcastStream::new(rx),
+			inner: self.inner.clone(),
+			lagged: false,
+		};
 		let (stream, drop_rx) = TrackDropStream::create(stream);
 
 		tokio::spawn({
@@ -328,11 +1631,13 @@ impl Player {
 					let mut statistics = statistics.write().await;
 					statistics.listeners += 1;
 					statistics.max_listeners = statistics.max_listeners.max(statistics.listeners);
+					statistics.mounts[output_index].listeners += 1;
 				}
 				drop_rx.await.unwrap();
 				{
 					let mut statistics = statistics.write().await;
 					statistics.listeners -= 1;
+					statistics.mounts[output_index].listeners -= 1;
 				}
 			}
 		});
@@ -340,18 +1645,90 @@ impl Player {
 		stream
 	}
 
+	/// `None` if requests are disabled
+	pub fn request(
+		&self,
+		id: usize,
+		client: std::net::IpAddr,
+	) -> Option<Result<(), requests::Rejection>> {
+		let requests = self.inner.requests.as_ref()?;
+		let library = self.library();
+		let Some(file) = library.playlist.get(id) else {
+			return Some(Err(requests::Rejection::NoSuchTrack));
+		};
+		let result = requests.lock().unwrap().push(id, file, client);
+		if result.is_ok() {
+			let _ = self.inner.queue_tx.send(());
+		}
+		Some(result)
+	}
+
+	pub fn read_requests<R>(&self, f: impl FnOnce(&VecDeque<requests::Request>) -> R) -> Option<R> {
+		self.inner.requests.as_ref().map(|x| f(x.lock().unwrap().queue()))
+	}
+
+	pub fn subscribe_queue(&self) -> tokio::sync::watch::Receiver<()> {
+		self.inner.queue_tx.subscribe()
+	}
+
+	/// library entries whose path or tags contain all words of the query
+	pub fn search(&self, query: &str, limit: usize) -> Vec<(usize, PathBuf)> {
+		let words = query.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>();
+		let library = self.library();
+		self.inner.metadata.read(|index| {
+			library
+				.playlist
+				.iter()
+				.enumerate()
+				.filter(|(_, x)| {
+					let mut text = x.to_string_lossy().to_lowercase();
+					if let Some(x) = index.get(*x).map(|x| &x.mediainfo) {
+						for tag in [&x.title, &x.artist, &x.album, &x.album_artist, &x.genre]
+							.into_iter()
+							.flatten()
+						{
+							text.push('\n');
+							text.push_str(&tag.to_lowercase());
+						}
+					}
+					words.iter().all(|x| text.contains(x))
+				})
+				.take(limit)
+				.map(|(id, x)| (id, x.clone()))
+				.collect()
+		})
+	}
+
+	pub fn library_statistics(&self) -> LibraryStatistics {
+		let library = self.library();
+		self.inner.metadata.read(|index| {
+			let indexed = library.playlist.iter().filter_map(|x| index.get(x)).collect::<Vec<_>>();
+			LibraryStatistics {
+				tracks: library.playlist.len(),
+				indexed: indexed.len(),
+				duration: Duration::from_secs_f64(
+					indexed.iter().filter_map(|x| x.mediainfo.duration).sum(),
+				),
+			}
+		})
+	}
+
 	pub fn subscribe_next_song(&self) -> tokio::sync::watch::Receiver<()> {
 		self.inner.next_song_tx.subscribe()
 	}
 
-	pub fn config(&self) -> &config::Config {
-		&self.inner.config
+	pub fn config(&self) -> Arc<config::Config> {
+		self.inner.config.read().unwrap().clone()
 	}
 
 	pub fn statistics(&self) -> &RwLock<Statistics> {
 		&self.inner.statistics
 	}
 
+	pub fn slow_listeners(&self) -> &SlowListeners {
+		&self.inner.slow_listeners
+	}
+
 	pub fn album_art(&self) -> &RwLock<AlbumImage> {
 		&self.inner.album_art
 	}
@@ -360,83 +1737,225 @@ impl Player {
 		f(self.inner.mediainfo.read().await.as_slice())
 	}
 
+	pub fn try_read_mediainfo<R, F: FnOnce(&[cmd::Mediainfo]) -> R>(&self, f: F) -> Option<R> {
+		self.inner.mediainfo.try_read().ok().map(|x| f(x.as_slice()))
+	}
+
+	/// the track is on air or skipped. a request is done, anything else moves the regular order on
+	fn advance(&self, index: usize, path: &Path) {
+		if !self.remove_request(path) {
+			self.inner.cursor.store(index, Ordering::Relaxed);
+		}
+	}
+
+	/// whether it was requested
+	fn remove_request(&self, path: &Path) -> bool {
+		let Some(requests) = &self.inner.requests else {
+			return false;
+		};
+		let removed = requests.lock().unwrap().remove(path);
+		if removed {
+			let _ = self.inner.queue_tx.send(());
+		}
+		removed
+	}
+
 	fn next(&self) {
-		let Inner { index, playlist, config, .. } = &*self.inner;
-		let shuffle = config.shuffle;
-
-		let mut loaded_index = index.load(Ordering::Relaxed);
-		if shuffle {
-			let mut rng = rand::thread_rng();
-			loaded_index = loop {
-				let new_index = rng.gen_range(0..playlist.len());
-				if new_index != loaded_index || playlist.len() == 1 {
-					break new_index;
-				}
-			}
-		} else {
-			loaded_index = (loaded_index + 1) % playlist.len();
+		self.inner.index.store(self.pick_next(), Ordering::Relaxed);
+	}
+
+	/// requests stay queued until they're on air, a pick that's thrown away doesn't lose them
+	fn pick_next(&self) -> usize {
+		if let Some(index) = self.inner.requests.as_ref().and_then(|x| x.lock().unwrap().peek()) {
+			return index;
 		}
 
-		index.store(loaded_index, Ordering::Relaxed);
+		let library = self.library();
+		let config = self.config();
+		let show_index = scheduled_show(&config);
+		let show = &library.shows[show_index];
+		let files = show.files.clone();
+		let cursor = self.inner.cursor.load(Ordering::Relaxed);
+
+		if let Some(albums) = albums(&config, show_index) {
+			return self
+				.inner
+				.metadata
+				.read(|index| album::next(albums, &library.playlist, files, cursor, index));
+		}
+		if self.inner.shuffle.load(Ordering::Relaxed) {
+			let playlist = &library.playlist[files.clone()];
+			let mut rotation = self.inner.rotation.lock().unwrap();
+			return files.start
+				+ self.inner.metadata.read(|index| {
+					rotation.pick(
+						show.key(),
+						playlist,
+						&config,
+						|x| index.get(x).map(|x| &x.mediainfo),
+						|x| weight(&config, show_index, x, index.get(x)),
+					)
+				});
+		}
+		if !files.contains(&cursor) {
+			// a different show is on now
+			return files.start;
+		}
+		files.start + (cursor - files.start + 1) % files.len()
 	}
 }
 
-/// try to read embedded album art and if it fails, try to read some image from the same directory
-async fn try_album_arts(input: impl AsRef<Path> + Send) -> Option<(PathBuf, Vec<u8>)> {
-	async fn try_album_art(input: impl AsRef<Path> + Send) -> Option<(PathBuf, Vec<u8>)> {
-		match cmd::album_art_png(input.as_ref()).await {
-			Ok(None) => None,
-			Ok(Some(buf)) => Some((input.as_ref().to_owned(), buf)),
-			Err(_) => None,
+/// how often a track comes up in shuffle compared to the others in its show
+fn weight(config: &config::Config, show: usize, path: &Path, entry: Option<&index::Entry>) -> f64 {
+	let dirs = match show.checked_sub(1) {
+		Some(x) => config.schedule.get(x).map_or(&*config.dirs, |x| &x.dirs),
+		None => &config.dirs,
+	};
+	let directory = files::directory(dirs, path).map_or(1.0, |x| x.weight.max(0.0));
+
+	let weighting = &config.weighting;
+	let rating =
+		entry.and_then(|x| x.mediainfo.rating).map_or(1.0, |x| (x / 3.0).powf(weighting.rating));
+	let days = entry
+		.and_then(|x| x.mtime().elapsed().ok())
+		.map_or(f32::INFINITY, |x| x.as_secs_f32() / 86400.0);
+	// new files start at `1 + new_boost` and are back to normal after `new_days`
+	let fade =
+		if weighting.new_days > 0.0 { (1.0 - days / weighting.new_days).max(0.0) } else { 0.0 };
+
+	f64::from(directory * rating * weighting.new_boost.mul_add(fade, 1.0))
+}
+
+/// album mode of a show
+fn albums(config: &config::Config, show: usize) -> Option<&config::AlbumConfig> {
+	show.checked_sub(1)
+		.map_or(config.albums.as_ref(), |x| config.schedule.get(x).and_then(|x| x.albums.as_ref()))
+}
+
+/// without ffmpeg only mp3 files can play, as they are. anything else would be skipped forever
+fn check_ffmpeg(config: &config::Config, library: &Library) -> Result<(), Error> {
+	if cmd::has_ffmpeg() {
+		return Ok(());
+	}
+	let mut needs = config
+		.outputs
+		.iter()
+		.filter(|x| x.codec != config::Codec::Mp3)
+		.map(|x| format!("output {} isn't mp3", x.mount))
+		.collect::<Vec<_>>();
+	if config.transcode_all || config.outputs.iter().any(|x| x.bitrate.is_some()) {
+		needs.push("transcoding".to_string());
+	}
+	if config.crossfade > 0.0 {
+		needs.push("crossfade".to_string());
+	}
+	if config.target_lufs.is_some() {
+		needs.push("target_lufs".to_string());
+	}
+	if config.sweepers.iter().any(|x| x.chance > 0.0) {
+		needs.push("sweepers".to_string());
+	}
+	let mp3 = |x: &PathBuf| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("mp3"));
+	for show in library.shows.iter() {
+		if !library.playlist[show.files.clone()].iter().any(mp3) {
+			needs.push(format!("{} has no mp3 files", show.name.as_deref().unwrap_or("playlist")));
 		}
 	}
-
-	// try from the file itself
-	if let Some(x) = try_album_art(input.as_ref()).await {
-		return Some(x);
+	if needs.is_empty() {
+		Ok(())
+	} else {
+		Err(Error::NeedsFfmpeg(needs))
 	}
+}
 
-	let mut futures_vec = vec![];
-	// then try from the same directory
-	if let Some(x) = input.as_ref().parent() {
-		let images = std::fs::read_dir(x)
-			.unwrap()
-			.flatten()
-			.filter(|x| {
-				let Ok(file_type) = x.file_type() else {
-					return false;
-				};
-				file_type.is_file()
-					&& x.path()
-						.extension()
-						.map_or(false, |x| x == "png" || x == "jpg" || x == "jpeg")
-			})
-			.collect::<Vec<_>>();
-		// try to read "cover.*" from the same directory
-		if let Some(x) =
-			images.iter().find(|x| x.path().file_stem().map_or(false, |x| x == "cover"))
-		{
-			futures_vec.push(try_album_art(x.path()));
-		} else {
-			for x in images {
-				futures_vec.push(try_album_art(x.path()));
-			}
+/// index into `Inner::shows` for the current local time
+fn scheduled_show(config: &config::Config) -> usize {
+	let now = chrono::Local::now().naive_local();
+	config.schedule.iter().position(|x| x.contains(now)).map_or(0, |x| x + 1)
+}
+
+/// embedded album art, or else the image next to the file
+async fn find_album_art(input: &Path, cover: Option<&Path>) -> Option<(PathBuf, Vec<u8>)> {
+	for x in std::iter::once(input).chain(cover) {
+		if let Ok(Some(data)) = cmd::album_art_png(x).await {
+			return Some((x.to_path_buf(), data));
 		}
 	}
+	None
+}
+
+/// "cover.*" in the directory, or else any image there. only looks at the names. blocks
+fn directory_cover(dir: &Path) -> Option<PathBuf> {
+	// the directory can be gone by now
+	let images = std::fs::read_dir(dir)
+		.into_iter()
+		.flatten()
+		.flatten()
+		.filter(|x| x.file_type().is_ok_and(|x| x.is_file()))
+		.map(|x| x.path())
+		.filter(|x| x.extension().is_some_and(|x| x == "png" || x == "jpg" || x == "jpeg"))
+		.collect::<Vec<_>>();
+	images
+		.iter()
+		.find(|x| x.file_stem().is_some_and(|x| x == "cover"))
+		.or_else(|| images.iter().min())
+		.cloned()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicUsize, Ordering};
 
-	let mut joins = JoinSet::new();
-	for x in futures_vec {
-		joins.spawn(x);
+	use super::*;
+
+	struct Counter {
+		control: tokio::sync::watch::Sender<TaskControlMessage>,
+		played: AtomicUsize,
 	}
 
-	let mut result = None;
-	while let Some(fut) = joins.join_next().await {
-		if let Ok(Some(x)) = fut {
-			result = Some(x);
-			joins.abort_all();
-			break;
+	#[async_trait::async_trait]
+	impl Task for Arc<Counter> {
+		fn control(&self) -> &tokio::sync::watch::Sender<TaskControlMessage> {
+			&self.control
+		}
+
+		async fn play_next(&self, _: tokio::time::Instant) {
+			self.played.fetch_add(1, Ordering::SeqCst);
+			tokio::time::sleep(Duration::from_millis(5)).await;
+		}
+
+		async fn play_live(&self, _: tokio::time::Instant) {}
+
+		fn rewind(&self) {}
+
+		fn grace_period(&self) -> Duration {
+			Duration::ZERO
 		}
 	}
 
-	result
+	#[test]
+	fn resumes_after_pause() {
+		let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+		runtime.block_on(async {
+			let counter = Arc::new(Counter {
+				control: tokio::sync::watch::channel(TaskControlMessage::Play).0,
+				played: AtomicUsize::new(0),
+			});
+			tokio::spawn(run_task(counter.clone()));
+			let wait = || tokio::time::sleep(Duration::from_millis(50));
+
+			wait().await;
+			assert!(counter.played.load(Ordering::SeqCst) > 0);
+
+			counter.control.send(TaskControlMessage::Pause).unwrap();
+			wait().await;
+			let paused = counter.played.load(Ordering::SeqCst);
+			wait().await;
+			assert_eq!(counter.played.load(Ordering::SeqCst), paused);
+
+			counter.control.send(TaskControlMessage::Play).unwrap();
+			wait().await;
+			assert!(counter.played.load(Ordering::SeqCst) > paused);
+		});
+	}
 }
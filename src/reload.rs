@@ -0,0 +1,71 @@
+use std::{
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use notify::{RecursiveMode, Watcher};
+
+use crate::{config, files, player::Player};
+
+// editors write files in more than one go
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// reloads the config file when it changes or on SIGHUP.
+/// listeners stay connected, settings that can't change live are reported
+pub async fn run(player: Player, path: PathBuf) -> notify::Result<()> {
+	let path = std::fs::canonicalize(path).map_err(notify::Error::io)?;
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	let mut watcher = notify::recommended_watcher({
+		let tx = tx.clone();
+		let path = path.clone();
+		move |x: notify::Result<notify::Event>| {
+			if x.is_ok_and(|x| !x.kind.is_access() && x.paths.contains(&path)) {
+				let _ = tx.send(());
+			}
+		}
+	})?;
+	// editors replace the file instead of writing to it, so the directory is watched
+	if let Some(x) = path.parent() {
+		watcher.watch(x, RecursiveMode::NonRecursive)?;
+	}
+
+	#[cfg(unix)]
+	tokio::spawn(async move {
+		use tokio::signal::unix::{signal, SignalKind};
+		let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+			return;
+		};
+		while hangup.recv().await.is_some() {
+			let _ = tx.send(());
+		}
+	});
+
+	while rx.recv().await.is_some() {
+		while tokio::time::timeout(SETTLE_TIME, rx.recv()).await == Ok(Some(())) {}
+		reload(&player, &path).await;
+	}
+	Ok(())
+}
+
+async fn reload(player: &Player, path: &Path) {
+	println!("Reloading config from {}", path.display());
+	let config = match config::load(path) {
+		Ok(x) => x,
+		Err(config::Error::Io(e)) => return println!("Could not load config: {}", e),
+		Err(config::Error::Parse(e)) => return println!("Could not parse config:\n{}", e),
+	};
+	let sweeper_files = {
+		let pools = config.sweepers.clone();
+		match tokio::task::spawn_blocking(move || files::collect_sweepers(&pools)).await.unwrap() {
+			Ok(x) => x,
+			Err(e) => return println!("Config not reloaded: {}", e),
+		}
+	};
+	match player.reload(config, sweeper_files).await {
+		Ok(restart) if restart.is_empty() => println!("Config reloaded"),
+		Ok(restart) => {
+			println!("Config reloaded. Restart to apply: {}", restart.join(", "));
+		}
+		Err(e) => println!("Config not reloaded: {:?}", e),
+	}
+}
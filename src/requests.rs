@@ -0,0 +1,126 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	fmt::{Display, Formatter},
+	net::IpAddr,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::config;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Request {
+	/// index into the library
+	pub id: usize,
+	pub filename: PathBuf,
+	pub requested_at: DateTime<Utc>,
+	// ids change when the library is rescanned
+	#[serde(skip)]
+	path: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum Rejection {
+	NoSuchTrack,
+	QueueFull,
+	AlreadyQueued,
+	RecentlyPlayed,
+	/// how long the client has to wait
+	TooSoon(Duration),
+}
+
+impl Display for Rejection {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::NoSuchTrack => write!(f, "no such track"),
+			Self::QueueFull => write!(f, "the queue is full"),
+			Self::AlreadyQueued => write!(f, "this song is already in the queue"),
+			Self::RecentlyPlayed => write!(f, "this song was played recently"),
+			Self::TooSoon(x) => write!(f, "wait {} seconds before the next request", x.as_secs()),
+		}
+	}
+}
+
+/// listener requests that play before the regular order
+pub struct Requests {
+	config: config::RequestConfig,
+	queue: VecDeque<Request>,
+	// last request of each client
+	clients: HashMap<IpAddr, Instant>,
+	// when each track was last played
+	played: HashMap<PathBuf, Instant>,
+}
+
+impl Requests {
+	pub fn new(config: config::RequestConfig) -> Self {
+		Self { config, queue: VecDeque::new(), clients: HashMap::new(), played: HashMap::new() }
+	}
+
+	pub fn push(&mut self, id: usize, path: &Path, client: IpAddr) -> Result<(), Rejection> {
+		let client_cooldown = Duration::from_secs(self.config.client_cooldown.into());
+		let replay_cooldown = Duration::from_secs(self.config.replay_cooldown.into());
+
+		if let Some(x) = self.clients.get(&client).map(Instant::elapsed) {
+			if x < client_cooldown {
+				return Err(Rejection::TooSoon(client_cooldown - x));
+			}
+		}
+		if self.queue.len() >= self.config.max_queue_len.get() {
+			return Err(Rejection::QueueFull);
+		}
+		if self.queue.iter().any(|x| x.id == id) {
+			return Err(Rejection::AlreadyQueued);
+		}
+		if self.played.get(path).is_some_and(|x| x.elapsed() < replay_cooldown) {
+			return Err(Rejection::RecentlyPlayed);
+		}
+
+		self.clients.insert(client, Instant::now());
+		self.queue.push_back(Request {
+			id,
+			filename: path.file_name().unwrap_or_default().into(),
+			requested_at: Utc::now(),
+			path: path.to_path_buf(),
+		});
+		Ok(())
+	}
+
+	/// the next request. it stays queued until `remove`
+	pub fn peek(&self) -> Option<usize> {
+		self.queue.front().map(|x| x.id)
+	}
+
+	/// takes the track out of the queue. returns whether it was requested
+	pub fn remove(&mut self, path: &Path) -> bool {
+		let len = self.queue.len();
+		self.queue.retain(|x| x.path != path);
+		self.queue.len() != len
+	}
+
+	pub fn played(&mut self, path: &Path) {
+		let now = Instant::now();
+		self.played.insert(path.to_path_buf(), now);
+		// nothing older than the cooldowns matters anymore
+		let client_cooldown = Duration::from_secs(self.config.client_cooldown.into());
+		let replay_cooldown = Duration::from_secs(self.config.replay_cooldown.into());
+		self.played.retain(|_, x| now - *x < replay_cooldown);
+		self.clients.retain(|_, x| now - *x < client_cooldown);
+	}
+
+	/// points the queue at the new library, dropping songs that are gone
+	pub fn remap(&mut self, id: impl Fn(&Path) -> Option<usize>) {
+		self.queue.retain_mut(|x| id(&x.path).map(|id| x.id = id).is_some());
+	}
+
+	pub const fn set_config(&mut self, config: config::RequestConfig) {
+		self.config = config;
+	}
+
+	pub const fn queue(&self) -> &VecDeque<Request> {
+		&self.queue
+	}
+}
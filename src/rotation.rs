@@ -0,0 +1,291 @@
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{cmd, config};
+
+// how far the rotation rules are scaled down, one step after another, when no track fits
+const RELAXATION: [f32; 4] = [1.0, 0.75, 0.5, 0.25];
+
+/// shuffle that plays every track of a show as often as its weight says before a new round starts
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Rotation {
+	/// how many more plays every track has in the current round, per show name.
+	/// "" is the main playlist. tracks that aren't in here yet have their full weight
+	#[serde(default)]
+	credits: HashMap<String, HashMap<PathBuf, f64>>,
+	/// most recent last
+	history: VecDeque<Play>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Play {
+	path: PathBuf,
+	at: DateTime<Utc>,
+	#[serde(default)]
+	artist: Option<String>,
+	#[serde(default)]
+	album: Option<String>,
+	#[serde(default)]
+	genre: Option<String>,
+}
+
+impl Play {
+	fn tag(&self, tag: config::RotationTag) -> Option<&str> {
+		match tag {
+			config::RotationTag::Artist => self.artist.as_deref(),
+			config::RotationTag::Album => self.album.as_deref(),
+			config::RotationTag::Genre => self.genre.as_deref(),
+		}
+	}
+}
+
+fn tag(mediainfo: &cmd::Mediainfo, tag: config::RotationTag) -> Option<&str> {
+	match tag {
+		config::RotationTag::Artist => mediainfo.artist.as_deref(),
+		config::RotationTag::Album => mediainfo.album.as_deref(),
+		config::RotationTag::Genre => mediainfo.genre.as_deref(),
+	}
+}
+
+impl Rotation {
+	/// index into `files` of a track that wasn't played in this round or the no-repeat window
+	/// and keeps to the rotation rules as far as possible
+	pub fn pick<'a>(
+		&mut self,
+		show: &str,
+		files: &[PathBuf],
+		config: &config::Config,
+		// tracks without tags can't break any rule
+		tags: impl Fn(&Path) -> Option<&'a cmd::Mediainfo>,
+		weight: impl Fn(&Path) -> f64,
+	) -> usize {
+		let credits = self.credits.entry(show.to_string()).or_default();
+		let available = |credits: &HashMap<PathBuf, f64>| {
+			(0..files.len())
+				.filter(|&x| {
+					credits.get(&files[x]).copied().unwrap_or_else(|| weight(&files[x])) >= 1.0
+				})
+				.collect::<Vec<_>>()
+		};
+		let mut unplayed = available(credits);
+		if unplayed.is_empty() {
+			// a new round. leftovers carry over so that weights below 1 add up over rounds
+			let mut next = files
+				.iter()
+				.map(|x| (x.clone(), credits.get(x).copied().unwrap_or(0.0).max(0.0) + weight(x)))
+				.collect::<HashMap<_, _>>();
+			unplayed = available(&next);
+			if unplayed.is_empty() {
+				// every weight is tiny, the fullest tracks go first
+				let max = next.values().copied().fold(0.0, f64::max);
+				next.values_mut().for_each(|x| *x += 1.0 - max);
+				unplayed = available(&next);
+			}
+			*credits = next;
+		}
+
+		let mut recent = self.recent(&config.no_repeat);
+		// the track on air can't follow itself, whatever the window
+		recent.extend(self.history.back().map(|x| x.path.as_path()));
+		let candidates = unplayed
+			.iter()
+			.copied()
+			.filter(|&x| !recent.contains(files[x].as_path()))
+			.collect::<Vec<_>>();
+		let mut rng = rand::thread_rng();
+		for scale in RELAXATION {
+			let fitting = candidates
+				.iter()
+				.filter(|&&x| {
+					tags(&files[x]).is_none_or(|tags| {
+						config.rotation_rules.iter().all(|rule| self.fits(rule, scale, tags))
+					})
+				})
+				.collect::<Vec<_>>();
+			if let Some(x) = fitting.choose(&mut rng) {
+				return **x;
+			}
+		}
+		if let Some(x) = candidates.choose(&mut rng) {
+			return *x;
+		}
+		// the window is longer than the bag, the track heard longest ago is the best there is
+		let last_played = |path: &Path| self.history.iter().rposition(|x| x.path == path);
+		unplayed.into_iter().min_by_key(|&x| last_played(&files[x])).unwrap_or(0)
+	}
+
+	fn fits(&self, rule: &config::RotationRule, scale: f32, mediainfo: &cmd::Mediainfo) -> bool {
+		let Some(value) = tag(mediainfo, rule.tag) else {
+			return true;
+		};
+		let same = |x: &Play| x.tag(rule.tag).is_some_and(|x| x.eq_ignore_ascii_case(value));
+
+		let now = Utc::now();
+		let minutes = Duration::from_secs_f32(rule.minutes.max(0.0) * 60.0 * scale);
+		let tracks = (rule.tracks as f32 * scale) as usize;
+		let recently = self
+			.history
+			.iter()
+			.rev()
+			.enumerate()
+			.take_while(|(i, x)| *i < tracks || age(now, x.at) <= minutes)
+			.any(|(_, x)| same(x));
+
+		// a smaller scale allows longer runs
+		let in_a_row = (rule.in_a_row as f32 / scale).ceil() as usize;
+		let run = rule.in_a_row > 0
+			&& self.history.len() >= in_a_row
+			&& self.history.iter().rev().take(in_a_row).all(same);
+
+		!recently && !run
+	}
+
+	pub fn played(
+		&mut self,
+		show: &str,
+		path: &Path,
+		mediainfo: &cmd::Mediainfo,
+		weight: f64,
+		config: &config::Config,
+	) {
+		let credits = self.credits.entry(show.to_string()).or_default();
+		let credit = credits.entry(path.to_path_buf()).or_insert(weight);
+		*credit = (*credit - 1.0).max(0.0);
+		self.history.push_back(Play {
+			path: path.to_path_buf(),
+			at: Utc::now(),
+			artist: mediainfo.artist.clone(),
+			album: mediainfo.album.clone(),
+			genre: mediainfo.genre.clone(),
+		});
+
+		// everything the no-repeat window and the rules look at is kept, the track on air always
+		let rules = config.rotation_rules.iter();
+		let tracks = rules
+			.clone()
+			.map(|x| x.tracks.max(x.in_a_row))
+			.fold(config.no_repeat.tracks.max(1), usize::max);
+		let hours = rules.map(|x| x.minutes / 60.0).fold(config.no_repeat.hours, f32::max);
+		let hours = Duration::from_secs_f32(hours.max(0.0) * 3600.0);
+		let now = Utc::now();
+		while self.history.len() > tracks
+			&& self.history.front().is_some_and(|x| age(now, x.at) > hours)
+		{
+			self.history.pop_front();
+		}
+	}
+
+	fn recent(&self, no_repeat: &config::NoRepeat) -> HashSet<&Path> {
+		let now = Utc::now();
+		let hours = Duration::from_secs_f32(no_repeat.hours.max(0.0) * 3600.0);
+		self.history
+			.iter()
+			.rev()
+			.enumerate()
+			.take_while(|(i, x)| *i < no_repeat.tracks || age(now, x.at) <= hours)
+			.map(|(_, x)| x.path.as_path())
+			.collect()
+	}
+}
+
+fn age(now: DateTime<Utc>, at: DateTime<Utc>) -> Duration {
+	(now - at).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn files(n: usize) -> Vec<PathBuf> {
+		(0..n).map(|x| PathBuf::from(format!("{x}.mp3"))).collect()
+	}
+
+	fn play(rotation: &mut Rotation, files: &[PathBuf], config: &config::Config) -> usize {
+		let x = rotation.pick("", files, config, |_| None, |_| 1.0);
+		rotation.played("", &files[x], &cmd::Mediainfo::default(), 1.0, config);
+		x
+	}
+
+	#[test]
+	fn every_track_once_per_round() {
+		let files = files(4);
+		let config = config::Config::default();
+		let mut rotation = Rotation::default();
+		let mut last = None;
+		for _ in 0..20 {
+			let round =
+				(0..files.len()).map(|_| play(&mut rotation, &files, &config)).collect::<Vec<_>>();
+			assert_eq!(round.iter().collect::<HashSet<_>>().len(), files.len());
+			// the last track of a round doesn't open the next one
+			assert_ne!(last, Some(round[0]));
+			last = round.last().copied();
+		}
+	}
+
+	#[test]
+	fn no_repeat_window() {
+		let files = files(5);
+		let config = config::Config {
+			no_repeat: config::NoRepeat { tracks: 3, hours: 0.0 },
+			..Default::default()
+		};
+		let mut rotation = Rotation::default();
+		let mut played = Vec::<usize>::new();
+		for _ in 0..100 {
+			let x = play(&mut rotation, &files, &config);
+			assert!(!played.iter().rev().take(3).any(|&y| y == x), "{x} in {played:?}");
+			played.push(x);
+		}
+	}
+
+	fn artist(artist: &str) -> cmd::Mediainfo {
+		cmd::Mediainfo { artist: Some(artist.to_string()), ..Default::default() }
+	}
+
+	// oldest first
+	fn history(plays: &[(&str, i64)]) -> Rotation {
+		let now = Utc::now();
+		let history = plays
+			.iter()
+			.map(|(artist, minutes_ago)| Play {
+				path: PathBuf::from(format!("{artist}.mp3")),
+				at: now - chrono::Duration::minutes(*minutes_ago),
+				artist: Some(artist.to_string()),
+				album: None,
+				genre: None,
+			})
+			.collect();
+		Rotation { history, ..Default::default() }
+	}
+
+	fn rule(minutes: f32, in_a_row: usize) -> config::RotationRule {
+		config::RotationRule { tag: config::RotationTag::Artist, minutes, tracks: 0, in_a_row }
+	}
+
+	#[test]
+	fn in_a_row_relaxes() {
+		let rotation = history(&[("a", 3), ("a", 2), ("a", 1)]);
+		let rule = rule(0.0, 2);
+		let fits = RELAXATION.map(|scale| rotation.fits(&rule, scale, &artist("a")));
+		// 2, then 3, then 4 and 8 in a row
+		assert_eq!(fits, [false, false, true, true]);
+		assert!(rotation.fits(&rule, 1.0, &artist("b")));
+	}
+
+	#[test]
+	fn minutes_relax() {
+		let rotation = history(&[("a", 40), ("b", 1)]);
+		let rule = rule(60.0, 0);
+		let fits = RELAXATION.map(|scale| rotation.fits(&rule, scale, &artist("a")));
+		// 60, then 45, then 30 and 15 minutes
+		assert_eq!(fits, [false, false, true, true]);
+		assert!(!rotation.fits(&rule, 0.25, &artist("b")));
+	}
+}
@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{cmd, files, player, rotation};
+
+/// what the player picks up again after a restart. saved as json
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+	#[serde(default)]
+	pub rotation: rotation::Rotation,
+	#[serde(default)]
+	pub playing: Option<Playing>,
+	#[serde(default)]
+	pub statistics: player::Statistics,
+	#[serde(default)]
+	pub slow_listeners: player::SlowListeners,
+	/// what `/mediainfo` lists, most recent first
+	#[serde(default)]
+	pub history: Vec<cmd::Mediainfo>,
+}
+
+/// the track that was on air
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Playing {
+	pub path: PathBuf,
+	/// seconds into the track
+	pub position: f32,
+}
+
+impl State {
+	/// the default state if the file is missing or broken
+	pub fn load(path: Option<&Path>) -> Self {
+		let Some(path) = path else {
+			return Self::default();
+		};
+		match std::fs::read(path) {
+			Ok(x) => serde_json::from_slice(&x).unwrap_or_else(|e| {
+				println!("Ignoring broken state {}: {e}", path.display());
+				Self::default()
+			}),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+			Err(e) => {
+				println!("Could not read state {}: {e}", path.display());
+				Self::default()
+			}
+		}
+	}
+
+	/// blocks
+	pub fn save(&self, path: &Path) -> std::io::Result<()> {
+		files::write(path, &serde_json::to_vec(self)?)
+	}
+}
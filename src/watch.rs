@@ -0,0 +1,119 @@
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use notify::{event::ModifyKind, EventKind, RecursiveMode, Watcher};
+
+use crate::{
+	config::{self, DirectoryConfig},
+	files,
+	player::Player,
+};
+
+// copies and editors fire bursts of events, they're applied once it's quiet for this long
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// keeps the library in sync with the directories as files are added, removed or renamed
+pub async fn run(player: Player) -> notify::Result<()> {
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	let mut watcher = notify::recommended_watcher(move |x| {
+		let _ = tx.send(x);
+	})?;
+
+	let config = player.config();
+	let mut roots = config
+		.dirs
+		.iter()
+		.chain(config.schedule.iter().flat_map(|x| x.dirs.iter()))
+		.map(|x| x.root.clone())
+		.collect::<Vec<_>>();
+	roots.sort();
+	roots.dedup();
+	for x in roots {
+		watcher.watch(&x, RecursiveMode::Recursive)?;
+	}
+
+	let mut changed = HashSet::new();
+	while let Some(event) = rx.recv().await {
+		collect_paths(event, &mut changed);
+		loop {
+			match tokio::time::timeout(SETTLE_TIME, rx.recv()).await {
+				Ok(Some(event)) => collect_paths(event, &mut changed),
+				Ok(None) => return Ok(()),
+				Err(_) => break,
+			}
+		}
+		if !changed.is_empty() {
+			apply(&player, std::mem::take(&mut changed)).await;
+		}
+	}
+	Ok(())
+}
+
+fn collect_paths(event: notify::Result<notify::Event>, changed: &mut HashSet<PathBuf>) {
+	match event {
+		// content changes are picked up by the index
+		Ok(event) => {
+			if matches!(
+				event.kind,
+				EventKind::Create(_)
+					| EventKind::Remove(_)
+					| EventKind::Modify(ModifyKind::Name(_))
+			) {
+				changed.extend(event.paths);
+			}
+		}
+		Err(e) => println!("Watching the library failed: {e}"),
+	}
+}
+
+async fn apply(player: &Player, changed: HashSet<PathBuf>) {
+	let (added, removed) = {
+		let player = player.clone();
+		tokio::task::spawn_blocking(move || {
+			let library = player.library();
+			let config = player.config();
+			let known = library.playlist.iter().collect::<HashSet<_>>();
+			let mut added = vec![];
+			let mut removed = HashSet::new();
+			for path in changed {
+				// renamed or deleted directories take everything in them along
+				removed.extend(
+					library
+						.playlist
+						.iter()
+						.filter(|x| x.starts_with(&path) && !x.exists())
+						.cloned(),
+				);
+				if path.is_dir() {
+					added.extend(files::collect(&[DirectoryConfig {
+						root: path,
+						mode: config::DirectoryConfigMode::Exclude(vec![].into_boxed_slice()),
+						weight: 1.0,
+					}]));
+				} else if path.is_file() {
+					added.push(path);
+				}
+			}
+			added.retain(|x| {
+				!known.contains(x)
+					&& (files::matches(&config.dirs, x)
+						|| config.schedule.iter().any(|y| files::matches(&y.dirs, x)))
+			});
+			added.sort();
+			added.dedup();
+			(added, removed)
+		})
+		.await
+		.unwrap()
+	};
+	if added.is_empty() && removed.is_empty() {
+		return;
+	}
+	match player.update_library(&added, &removed) {
+		Ok(len) => println!(
+			"Library updated: {} added, {} removed, {len} songs",
+			added.len(),
+			removed.len()
+		),
+		Err(e) => println!("Library not updated: {e:?}"),
+	}
+}